@@ -0,0 +1,65 @@
+use polars::prelude::*;
+use tracing::{ info, error };
+
+#[tokio::main]
+async fn main() {
+    use load_data::data::{aggregate, read_csvs};
+    use load_data::event_study::{event_study, EventStudySpec};
+
+    tracing_subscriber::fmt().init();
+
+    let files = vec![
+        "2022-04.csv",
+        "2022-05.csv",
+        "2022-06.csv",
+    ];
+    let sigma = 2.0f64;
+    let target_pnl = 0.01f64;
+    let duration = 20i64;
+
+    let table = read_csvs(files)
+        .and_then(|lf| aggregate(lf, sigma, target_pnl, duration).collect())
+        .and_then(|df| event_study(&df, "abnormal volume", &EventStudySpec::default()));
+    match table {
+        Ok(study) => {
+            let csv_file = std::fs::File::create("event-study.csv").unwrap();
+            CsvWriter::new(csv_file)
+                .has_header(true)
+                .finish(&mut study.summary.clone())
+                .unwrap();
+            info!("{:?}", study.summary);
+            match plot_car(&study.summary, "event-study.svg") {
+                Ok(()) => info!("plotted to event-study.svg"),
+                Err(e) => error!("{:?}", e),
+            }
+        },
+        Err(e) => error!("{:?}", e),
+    }
+}
+
+// the mean and the confidence band of the cumulative abnormal return by offset
+fn plot_car(summary: &DataFrame, path: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    use plotters::prelude::*;
+
+    let offsets = summary.column("offset")?.i32()?;
+    let points = |name: &str| -> std::result::Result<Vec<(i32, f64)>, PolarsError> {
+        Ok(offsets.into_no_null_iter().zip(summary.column(name)?.f64()?.into_no_null_iter()).collect())
+    };
+    let (mean, lower, upper) = (points("mean car")?, points("car lower")?, points("car upper")?);
+
+    let area = SVGBackend::new(path, (1024, 760)).into_drawing_area();
+    area.fill(&WHITE)?;
+    let y_axis = lower.iter().map(|p| p.1).fold(f64::INFINITY, f64::min)..upper.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+    let mut chart = ChartBuilder::on(&area)
+        .set_label_area_size(LabelAreaPosition::Left, 60u32)
+        .set_label_area_size(LabelAreaPosition::Bottom, 40u32)
+        .margin(15u32)
+        .caption("Cumulative abnormal return around abnormal volume", ("sans", 20u32))
+        .build_cartesian_2d(offsets.min().unwrap_or(0)..offsets.max().unwrap_or(0), y_axis)?;
+    chart.configure_mesh().x_desc("Offset").y_desc("CAR").draw()?;
+    chart.draw_series(LineSeries::new(lower, RED.mix(0.5)))?;
+    chart.draw_series(LineSeries::new(upper, RED.mix(0.5)))?;
+    chart.draw_series(LineSeries::new(mean, &BLUE))?;
+    area.present()?;
+    Ok(())
+}
@@ -34,6 +34,7 @@ async fn main() {
 
 pub mod data {
     use polars::prelude::*;
+    use load_data::data::Trend;
 
     pub fn load_data<'a, I, T>(files: I, sigma: f64) -> Result<DataFrame>
         where
@@ -119,27 +120,24 @@ pub mod data {
                                         when(
                                             col("rising float").gt_eq(lit(0.01f64)).and(col("falling float").lt_eq(lit(-0.01f64)))
                                         ).then(
-                                            lit(3u32) // "Chaos"
+                                            Trend::Chaos.lit()
                                         ).when(
                                             col("rising float").gt_eq(lit(0.01f64))
                                         ).then(
-                                            lit(2u32) // "Bull"
+                                            Trend::Bull.lit()
                                         ).when(
                                             col("falling float").lt_eq(lit(-0.01f64))
                                         ).then(
-                                            lit(1u32) // "Bear"
+                                            Trend::Bear.lit()
                                         ).otherwise(
-                                            lit(0u32) // "Unknown"
-                                        ).alias("trend"),
+                                            Trend::Unknown.lit()
+                                        )
+                                        .cast(Trend::dtype())
+                                        .alias("trend"),
                                     )
                                     .with_column(
                                         col("trend").filter(
-                                            col("trend").eq(lit(3u32))
-                                            .or(
-                                                col("trend").eq(lit(2u32))
-                                            ).or(
-                                                col("trend").eq(lit(1u32))
-                                            )
+                                            col("trend").neq(Trend::Unknown.lit())
                                         ).first()
                                         .over([col("group")])
                                         .alias("first reached trend")
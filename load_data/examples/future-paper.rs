@@ -0,0 +1,142 @@
+use load_data::engine::{Bar, Context, Order, Side, Strategy};
+use tracing::{ info, error };
+
+// Momentum on the primary signal of `aggregate`, out at the target pnl from the entry.
+struct Momentum {
+    quantity: f64,
+    target_pnl: f64,
+}
+
+impl Strategy for Momentum {
+    fn on_bar(&mut self, bar: &Bar, ctx: &mut Context) {
+        if ctx.open_orders().count() > 0 {
+            return;
+        }
+        let position = ctx.position();
+        if position != 0f64 {
+            let pnl = position.signum() * (bar.close / ctx.account().entry_price - 1f64);
+            if pnl.abs() >= self.target_pnl {
+                let side = if position > 0f64 { Side::Sell } else { Side::Buy };
+                ctx.submit(Order::market(side, position.abs()).reduce_only());
+            }
+            return;
+        }
+        let flag = |name: &str| bar.feature(name) == Some(1f64);
+        if flag("abnormal volume") && flag("upper band touched") != flag("lower band touched") {
+            let side = if flag("upper band touched") { Side::Buy } else { Side::Sell };
+            ctx.submit(Order::market(side, self.quantity));
+        }
+    }
+}
+
+// Serves the recorded messages of `paper-klines.txt` to every client connecting to `address`, for an
+// offline run of the live path.
+async fn serve(file: &str, address: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    use futures::SinkExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let messages = std::fs::read_to_string(file)?;
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!("replaying {} on ws://{}", file, address);
+    loop {
+        let (stream, client) = listener.accept().await?;
+        let messages = messages.clone();
+        tokio::spawn(async move {
+            let replayed = async {
+                let mut socket = tokio_tungstenite::accept_async(stream).await?;
+                for line in messages.lines().filter(|line| !line.trim().is_empty()) {
+                    socket.send(Message::Text(line.to_string())).await?;
+                }
+                socket.close(None).await
+            };
+            match replayed.await {
+                Ok(()) => info!("replayed to {}", client),
+                Err(e) => error!("{:?}", e),
+            }
+        });
+    }
+}
+
+// Paper trades BTCUSDT on the 15m klines of the futures websocket, recorded to `paper-klines.txt`:
+//   future-paper [endpoint]                 live, or against another endpoint like ws://127.0.0.1:9001
+//   future-paper serve <file> [address]     a local server replaying recorded klines to the live path
+#[allow(clippy::result_large_err)] // the error type of the websocket handler
+#[tokio::main]
+async fn main() {
+    use binance::config::Config;
+    use binance::websockets::{kline_stream, WebSockets};
+    use binance::ws_model::WebsocketEvent;
+    use load_data::costs::{CostModel, Fees, Slippage};
+    use load_data::data::aggregate;
+    use load_data::engine::EngineSpec;
+    use load_data::paper::{kline_handler, PaperSpec, PaperTrader};
+    use polars::prelude::*;
+    use std::io::Write;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc::channel;
+
+    tracing_subscriber::fmt().init();
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(|a| a == "serve").unwrap_or(false) {
+        let file = args.get(1).map(String::as_str).unwrap_or("paper-klines.txt");
+        let address = args.get(2).map(String::as_str).unwrap_or("127.0.0.1:9001");
+        if let Err(e) = serve(file, address).await {
+            error!("{:?}", e);
+        }
+        return;
+    }
+    let live = Config::default().futures_ws_endpoint;
+    let endpoint = args.first().cloned().unwrap_or_else(|| live.clone());
+
+    let engine = EngineSpec { costs: Some(CostModel::new(Fees::vip(0), Slippage::Fixed(1.0))), ..Default::default() };
+    let spec = PaperSpec { state: Some("paper-state.txt".into()), ..PaperSpec::new(engine, 200) };
+    let (sigma, target_pnl, duration) = (2.0, 0.01, 20);
+    let mut trader = match PaperTrader::new(spec, move |lf: LazyFrame| aggregate(lf, sigma, target_pnl, duration)) {
+        Ok(trader) => trader,
+        Err(e) => return error!("{:?}", e),
+    };
+    let mut strategy = Momentum { quantity: 0.01, target_pnl };
+
+    // the websocket sends the closed klines to the trader on its own thread
+    let (sender, mut receiver) = channel();
+    let paper = tokio::task::spawn_blocking(move || {
+        if let Err(e) = trader.run(&mut receiver, &mut strategy) {
+            error!("{:?}", e);
+        }
+        info!("equity {:.2}, {} fills", trader.equity(), trader.fills().len());
+    });
+    // only the live klines are recorded
+    let mut record = if endpoint == live {
+        match std::fs::OpenOptions::new().create(true).append(true).open("paper-klines.txt") {
+            Ok(file) => Some(file),
+            Err(e) => return error!("{:?}", e),
+        }
+    } else {
+        None
+    };
+    let mut handler = kline_handler(sender);
+    let handle = move |event: WebsocketEvent| {
+        if let WebsocketEvent::Kline(kline) = &event {
+            if kline.kline.is_final_bar {
+                info!("{} closed at {}", kline.kline.start_time, kline.kline.close);
+            }
+        }
+        if let Some(record) = record.as_mut() {
+            writeln!(record, "{}", serde_json::to_string(&event)?)?;
+        }
+        handler(event)
+    };
+    let running = AtomicBool::new(true);
+    let mut socket: WebSockets<'_, WebsocketEvent> =
+        WebSockets::new_with_options(handle, Config::default().set_futures_ws_endpoint(endpoint));
+    if let Err(e) = socket.connect_futures(&kline_stream("btcusdt", "15m")).await {
+        return error!("{:?}", e);
+    }
+    // until the server disconnects, the end of a replay
+    if let Err(e) = socket.event_loop(&running).await {
+        error!("{:?}", e);
+    }
+    drop(socket);
+    let _ = paper.await;
+}
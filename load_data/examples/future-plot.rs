@@ -21,12 +21,13 @@ async fn main() {
 pub mod plot {
     use polars::prelude::*;
     use plotters::prelude::*;
+    use load_data::data::Trend;
     const OUT_FILE_NAME: &'static str = "./scatters.svg";
 
     pub fn plot_trend(data: &DataFrame) -> std::result::Result<(), Box<dyn std::error::Error>> {
         let primaries = data.column("primary").unwrap().f64().unwrap();
         let secondaries = data.column("secondary").unwrap().f64().unwrap();
-        let trends = data.column("trend").unwrap().u32().unwrap();
+        let trends = Trend::from_series(data.column("trend").unwrap())?;
 
         let area = SVGBackend::new(OUT_FILE_NAME, (1024, 760)).into_drawing_area();
 
@@ -55,10 +56,10 @@ pub mod plot {
                     TriangleMarker::new(
                         (primaries.get(row).unwrap(), secondaries.get(row).unwrap()),
                         5i32,
-                        match trends.get(row) {
-                            Some(2u32) => BLUE.mix(0.5).filled(),
-                            Some(1u32) => RED.mix(0.5).filled(),
-                            Some(3u32) => GREEN.mix(0.5).filled(),
+                        match trends[row] {
+                            Some(Trend::Bull) => BLUE.mix(0.5).filled(),
+                            Some(Trend::Bear) => RED.mix(0.5).filled(),
+                            Some(Trend::Chaos) => GREEN.mix(0.5).filled(),
                             _ => YELLOW.mix(0.5).filled(),
                         }
                     )
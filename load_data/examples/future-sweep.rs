@@ -0,0 +1,41 @@
+use tracing::{ info, error };
+
+#[tokio::main]
+async fn main() {
+    use load_data::data::{read_csvs, Interval};
+    use load_data::meta_labeling::BandSide;
+    use load_data::sweep::{sweep, trials_to_frame, write_table, Cache, Objective, SignalGrid, SweepSpec};
+    use std::time::Instant;
+
+    tracing_subscriber::fmt().init();
+
+    let files = vec![
+        "2022-04.csv",
+        "2022-05.csv",
+        "2022-06.csv",
+    ];
+    let grid = SignalGrid {
+        sigma: vec![1.5, 2.0, 2.5, 3.0],
+        target_pnl: vec![0.005, 0.01, 0.02],
+        duration: vec![10, 20, 40, 80],
+        hold: vec![4, 8, 16],
+        band_side: vec![BandSide::Momentum, BandSide::Reversion],
+    };
+    let spec = SweepSpec::new(Interval::M15, Objective::Sharpe);
+
+    let started = Instant::now();
+    let table = read_csvs(files)
+        .and_then(|lf| lf.collect())
+        .and_then(|df| sweep(&Cache::new(df), &grid.specs(), &spec, None))
+        .and_then(|trials| trials_to_frame(&trials, spec.objective));
+    match table {
+        Ok(mut table) => {
+            info!("{} trials in {:?}", table.height(), started.elapsed());
+            info!("{:?}", table.head(Some(10)));
+            if let Err(e) = write_table(&mut table, "sweep.csv") {
+                error!("{:?}", e);
+            }
+        },
+        Err(e) => error!("{:?}", e),
+    }
+}
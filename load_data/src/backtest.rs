@@ -0,0 +1,255 @@
+use polars::prelude::*;
+
+use crate::costs::{Cost, CostModel, Entry};
+use crate::data::{f64_column, timestamp_series};
+use crate::engine::Liquidity;
+
+// Vectorized backtest of a target position column, e.g. -1..1 or a leverage, decided at the close
+// of each bar. Everything stays lazy so it runs over years of 1m candles.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fill {
+    NextOpen, // the target of a bar is filled at the open of the next bar
+    Close,    // the target of a bar is filled at its close
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktestSpec {
+    pub position: String,
+    pub fill: Fill,
+}
+
+impl BacktestSpec {
+    pub fn new(position: &str, fill: Fill) -> Self {
+        Self { position: position.to_string(), fill }
+    }
+}
+
+// position held over a bar, filled from the target of the previous bar
+fn held_position(spec: &BacktestSpec) -> Expr {
+    col(&spec.position).cast(DataType::Float64).shift(1).fill_null(lit(0f64)).alias("held position")
+}
+
+// the price the held position of a bar was filled at
+fn fill_price(spec: &BacktestSpec) -> Expr {
+    match spec.fill {
+        Fill::NextOpen => col("open"),
+        Fill::Close => col("close").shift(1),
+    }
+    .alias("fill price")
+}
+
+// the return over the span the held position of a bar is held for
+fn bar_return(spec: &BacktestSpec) -> Expr {
+    match spec.fill {
+        Fill::NextOpen => col("open").shift(-1) / col("open") - lit(1f64),
+        Fill::Close => col("close") / col("close").shift(1) - lit(1f64),
+    }
+    .alias("bar return")
+}
+
+fn strategy_return() -> Expr {
+    (col("held position") * col("bar return")).fill_null(lit(0f64)).alias("strategy return")
+}
+
+fn turnover() -> Expr {
+    (col("held position") - col("held position").shift(1).fill_null(lit(0f64))).abs().alias("turnover")
+}
+
+fn equity() -> Expr {
+    (lit(1f64) + col("strategy return")).cumprod(false).alias("equity")
+}
+
+pub fn backtest(lf: LazyFrame, spec: &BacktestSpec) -> LazyFrame {
+    lf.with_columns([held_position(spec), fill_price(spec), bar_return(spec)])
+        .with_columns([strategy_return(), turnover()])
+        .with_column(equity())
+}
+
+// Costs of a collected backtest run with `capital`, every change of position taking liquidity at the
+// fill price. Adds the fees, slippage and funding of each bar as returns on the equity of the previous bar
+// and the net return and equity after them.
+pub fn with_costs(df: &DataFrame, spec: &BacktestSpec, model: &CostModel, capital: f64) -> Result<(DataFrame, Vec<Entry>)> {
+    let timestamp = df.column("timestamp")?.cast(&DataType::Int64)?;
+    let timestamp = timestamp.i64()?.into_no_null_iter().collect::<Vec<_>>();
+    let held = f64_column(df, "held position")?;
+    let turnover = f64_column(df, "turnover")?;
+    let price = f64_column(df, "fill price")?;
+    let open = f64_column(df, "open")?;
+    let volume = f64_column(df, "volume")?;
+    let gross = f64_column(df, "strategy return")?;
+    let spread = match model.slippage.column() {
+        Some(column) => Some(f64_column(df, column)?),
+        None => None,
+    };
+    let fundings = model.funding.as_ref().map(|f| f.per_bar(&timestamp));
+
+    let n = df.height();
+    let (mut fee, mut slippage, mut funding) = (vec![0f64; n], vec![0f64; n], vec![0f64; n]);
+    let (mut net, mut equity) = (Vec::with_capacity(n), Vec::with_capacity(n));
+    let mut ledger = Vec::new();
+    let mut last = 1f64;
+    for i in 0..n {
+        let account = last * capital;
+        let mut entries = Vec::new();
+        // position held at the open of the bar, the next open fill only happens at it
+        let at_open = match spec.fill {
+            Fill::NextOpen if i == 0 => 0f64,
+            Fill::NextOpen => held[i - 1],
+            Fill::Close => held[i],
+        };
+        if let Some(fundings) = &fundings {
+            for &(t, rate) in &fundings[i] {
+                entries.push(model.funding(t, rate, at_open * account / open[i], open[i]));
+            }
+        }
+        if turnover[i] > 0f64 && price[i].is_finite() {
+            let quantity = turnover[i] * account / price[i];
+            let spread = spread.as_ref().map(|s| s[i]);
+            entries.extend(model.fill(timestamp[i], Liquidity::Taker, quantity, price[i], volume[i], spread));
+        }
+        for entry in &entries {
+            let r = entry.amount / account;
+            match entry.cost {
+                Cost::Fee | Cost::Liquidation => fee[i] += r,
+                Cost::Slippage => slippage[i] += r,
+                Cost::Funding => funding[i] += r,
+            }
+        }
+        let r = gross[i] + fee[i] + slippage[i] + funding[i];
+        last *= 1f64 + r;
+        net.push(r);
+        equity.push(last);
+        ledger.extend(entries);
+    }
+
+    let mut df = df.clone();
+    df.hstack_mut(&[
+        Series::new("fee return", fee),
+        Series::new("slippage return", slippage),
+        Series::new("funding return", funding),
+        Series::new("net return", net),
+        Series::new("net equity", equity),
+    ])?;
+    Ok((df, ledger))
+}
+
+fn sign(x: f64) -> f64 {
+    if x > 0f64 {
+        1f64
+    } else if x < 0f64 {
+        -1f64
+    } else {
+        0f64
+    }
+}
+
+// Round trips of a collected backtest: a trade lasts while the held position keeps its sign.
+pub fn trades(df: &DataFrame) -> Result<DataFrame> {
+    let timestamp = df.column("timestamp")?.cast(&DataType::Int64)?;
+    let timestamp = timestamp.i64()?.into_no_null_iter().collect::<Vec<_>>();
+    let held = f64_column(df, "held position")?;
+    let price = f64_column(df, "fill price")?;
+    let returns = f64_column(df, "strategy return")?;
+
+    let (mut entries, mut exits, mut sides, mut sizes) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let (mut entry_prices, mut exit_prices, mut pnl, mut bars) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let mut open: Option<usize> = None;
+    for i in 0..=held.len() {
+        let side = held.get(i).copied().map(sign).unwrap_or(0f64);
+        if let Some(start) = open {
+            if side != sign(held[start]) {
+                entries.push(timestamp[start]);
+                exits.push(if i < held.len() { Some(timestamp[i]) } else { None });
+                sides.push(sign(held[start]));
+                sizes.push(held[start..i].iter().map(|h| h.abs()).fold(0f64, f64::max));
+                entry_prices.push(price[start]);
+                exit_prices.push(price.get(i).copied().filter(|p| p.is_finite()));
+                pnl.push(returns[start..i].iter().fold(1f64, |acc, r| acc * (1f64 + r)) - 1f64);
+                bars.push((i - start) as u32);
+                open = None;
+            }
+        }
+        if open.is_none() && side != 0f64 {
+            open = Some(i);
+        }
+    }
+
+    DataFrame::new(vec![
+        timestamp_series("entry timestamp", entries)?,
+        Series::new("exit timestamp", exits).cast(&DataType::Datetime(TimeUnit::Milliseconds, Some("UTC".into())))?,
+        Series::new("side", sides),
+        Series::new("max size", sizes),
+        Series::new("entry price", entry_prices),
+        Series::new("exit price", exit_prices),
+        Series::new("return", pnl),
+        Series::new("bars", bars),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::costs::{Fees, FundingRates, Slippage};
+
+    fn frame() -> DataFrame {
+        df![
+            "timestamp" => (0..6i64).collect::<Vec<_>>(),
+            "open" => [100.0, 101.0, 102.0, 100.0, 99.0, 100.0],
+            "close" => [101.0, 102.0, 100.0, 99.0, 100.0, 101.0],
+            "target" => [1.0, 1.0, -0.5, 0.0, 0.0, 0.0],
+        ].unwrap()
+    }
+
+    #[test]
+    fn test_next_open() {
+        let df = backtest(frame().lazy(), &BacktestSpec::new("target", Fill::NextOpen)).collect().unwrap();
+        let get = |name: &str| f64_column(&df, name).unwrap();
+
+        assert_eq!(get("held position"), vec![0.0, 1.0, 1.0, -0.5, 0.0, 0.0]);
+        // long from the open of 101 to the open of 100 through 102, then short to 99
+        let expected = [0.0, 102.0 / 101.0 - 1.0, 100.0 / 102.0 - 1.0, -0.5 * (99.0 / 100.0 - 1.0), 0.0, 0.0];
+        for (r, e) in get("strategy return").iter().zip(expected) {
+            assert!((r - e).abs() < 1e-12);
+        }
+        assert_eq!(get("turnover"), vec![0.0, 1.0, 0.0, 1.5, 0.5, 0.0]);
+        let equity = get("equity");
+        assert!((equity[5] - 100.0 / 101.0 * 1.005).abs() < 1e-12);
+
+        let trades = trades(&df).unwrap();
+        assert_eq!(trades.height(), 2);
+        assert_eq!(f64_column(&trades, "side").unwrap(), vec![1.0, -1.0]);
+        assert_eq!(f64_column(&trades, "entry price").unwrap(), vec![101.0, 100.0]);
+        assert_eq!(f64_column(&trades, "exit price").unwrap(), vec![100.0, 99.0]);
+        assert!((f64_column(&trades, "return").unwrap()[0] - (100.0 / 101.0 - 1.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_costs() {
+        let df = backtest(frame().lazy(), &BacktestSpec::new("target", Fill::NextOpen)).collect().unwrap();
+        let df = df.lazy().with_column(lit(1f64).alias("volume")).collect().unwrap();
+        // funding at the open of the third bar, while long from the second
+        let model = CostModel::new(Fees::vip(0), Slippage::Fixed(1.0)).with_funding(FundingRates { timestamp: vec![2], rate: vec![0.0001] });
+        let (df, ledger) = with_costs(&df, &BacktestSpec::new("target", Fill::NextOpen), &model, 1000.0).unwrap();
+        let get = |name: &str| f64_column(&df, name).unwrap();
+
+        // the turnover of 1 and 1.5 pays 5 bps in fees and slippage
+        assert!((get("fee return")[1] + 0.0004).abs() < 1e-12);
+        assert!((get("slippage return")[3] + 1.5 * 0.0001).abs() < 1e-12);
+        assert!((get("funding return")[2] + 0.0001).abs() < 1e-12);
+        assert_eq!(get("fee return")[2], 0.0);
+        assert_eq!(ledger.len(), 2 * 3 + 1);
+        let gross = get("equity")[5];
+        let net = get("net equity")[5];
+        assert!(net < gross && (gross - net) < 0.003);
+    }
+
+    #[test]
+    fn test_close() {
+        let df = backtest(frame().lazy(), &BacktestSpec::new("target", Fill::Close)).collect().unwrap();
+        let returns = f64_column(&df, "strategy return").unwrap();
+        assert!((returns[1] - (102.0 / 101.0 - 1.0)).abs() < 1e-12);
+        assert!((returns[3] + 0.5 * (99.0 / 100.0 - 1.0)).abs() < 1e-12);
+        assert_eq!(f64_column(&trades(&df).unwrap(), "entry price").unwrap(), vec![101.0, 100.0]);
+    }
+}
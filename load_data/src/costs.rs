@@ -0,0 +1,256 @@
+use polars::prelude::*;
+
+use crate::data::{f64_column, timestamp_series};
+use crate::engine::Liquidity;
+
+// Trading costs of Binance USDT-M futures: fees by VIP tier, funding at the 8h funding timestamps
+// and slippage. Costs are kept apart from the pnl of the fills as ledger entries.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeTier {
+    pub volume: f64, // 30 day volume in USDT from which the tier applies
+    pub maker: f64,
+    pub taker: f64,
+}
+
+// USDT-M futures VIP 0 to 9
+pub const FEE_TIERS: [FeeTier; 10] = [
+    FeeTier { volume: 0.0, maker: 0.00020, taker: 0.00040 },
+    FeeTier { volume: 15e6, maker: 0.00016, taker: 0.00040 },
+    FeeTier { volume: 50e6, maker: 0.00014, taker: 0.00035 },
+    FeeTier { volume: 100e6, maker: 0.00012, taker: 0.00032 },
+    FeeTier { volume: 600e6, maker: 0.00010, taker: 0.00030 },
+    FeeTier { volume: 1e9, maker: 0.00008, taker: 0.00027 },
+    FeeTier { volume: 2.5e9, maker: 0.00006, taker: 0.00025 },
+    FeeTier { volume: 5e9, maker: 0.00004, taker: 0.00022 },
+    FeeTier { volume: 12.5e9, maker: 0.00002, taker: 0.00020 },
+    FeeTier { volume: 25e9, maker: 0.00000, taker: 0.00017 },
+];
+
+// paying the fees in BNB
+pub const BNB_DISCOUNT: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fees {
+    pub tier: FeeTier,
+    pub bnb: bool,
+}
+
+impl Fees {
+    pub fn vip(level: usize) -> Self {
+        Self { tier: FEE_TIERS[level.min(FEE_TIERS.len() - 1)], bnb: false }
+    }
+
+    pub fn for_volume(volume: f64) -> Self {
+        let tier = FEE_TIERS.iter().rev().find(|t| volume >= t.volume).copied().unwrap_or(FEE_TIERS[0]);
+        Self { tier, bnb: false }
+    }
+
+    pub fn with_bnb(self) -> Self {
+        Self { bnb: true, ..self }
+    }
+
+    // fraction of the notional
+    pub fn rate(&self, liquidity: Liquidity) -> f64 {
+        let rate = match liquidity {
+            Liquidity::Maker => self.tier.maker,
+            Liquidity::Taker => self.tier.taker,
+        };
+        if self.bnb {
+            rate * (1f64 - BNB_DISCOUNT)
+        } else {
+            rate
+        }
+    }
+}
+
+impl Default for Fees {
+    fn default() -> Self {
+        Self::vip(0)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Slippage {
+    #[default]
+    Zero,
+    Fixed(f64),                    // in basis points of the price
+    Spread(String),                // column of relative bid-ask spreads, half of it is paid
+    Participation { impact: f64 }, // impact * sqrt(quantity / volume of the bar)
+}
+
+impl Slippage {
+    // fraction of the notional paid by a taker fill, `spread` is the value of the spread column if any
+    pub fn rate(&self, quantity: f64, volume: f64, spread: Option<f64>) -> f64 {
+        match self {
+            Slippage::Zero => 0f64,
+            Slippage::Fixed(bps) => bps * 1e-4,
+            Slippage::Spread(_) => spread.filter(|s| s.is_finite()).unwrap_or(0f64) / 2f64,
+            Slippage::Participation { impact } if volume > 0f64 => impact * (quantity.abs() / volume).sqrt(),
+            // nothing traded in the bar, take the whole impact
+            Slippage::Participation { impact } => *impact,
+        }
+    }
+
+    pub fn column(&self) -> Option<&str> {
+        match self {
+            Slippage::Spread(column) => Some(column),
+            _ => None,
+        }
+    }
+}
+
+// Funding history, e.g. from `futures_funding_rate` of the futures api.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FundingRates {
+    pub timestamp: Vec<i64>,
+    pub rate: Vec<f64>,
+}
+
+impl FundingRates {
+    // `fundingTime` in ms (or a datetime) and `fundingRate`
+    pub fn from_frame(df: &DataFrame) -> Result<Self> {
+        let df = df.sort(["fundingTime"], false)?;
+        let timestamp = df.column("fundingTime")?.cast(&DataType::Int64)?;
+        Ok(Self {
+            timestamp: timestamp.i64()?.into_no_null_iter().collect(),
+            rate: f64_column(&df, "fundingRate")?,
+        })
+    }
+
+    // fundings in (after, upto]
+    pub fn between(&self, after: i64, upto: i64) -> impl Iterator<Item = (i64, f64)> + '_ {
+        let start = self.timestamp.partition_point(|t| *t <= after);
+        let end = self.timestamp.partition_point(|t| *t <= upto);
+        (start..end).map(|i| (self.timestamp[i], self.rate[i]))
+    }
+
+    // The fundings of each bar are the ones since the open of the previous bar, settled at its open
+    // on the position held into the bar.
+    pub fn per_bar(&self, timestamp: &[i64]) -> Vec<Vec<(i64, f64)>> {
+        (0..timestamp.len())
+            .map(|i| {
+                let after = if i == 0 { timestamp[0] - 1 } else { timestamp[i - 1] };
+                self.between(after, timestamp[i]).collect()
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostModel {
+    pub fees: Fees,
+    pub slippage: Slippage,
+    pub funding: Option<FundingRates>,
+}
+
+impl CostModel {
+    pub fn new(fees: Fees, slippage: Slippage) -> Self {
+        Self { fees, slippage, funding: None }
+    }
+
+    pub fn with_funding(self, funding: FundingRates) -> Self {
+        Self { funding: Some(funding), ..self }
+    }
+
+    // costs of a fill as ledger entries, makers rest at their price and pay no slippage
+    pub fn fill(&self, timestamp: i64, liquidity: Liquidity, quantity: f64, price: f64, volume: f64, spread: Option<f64>) -> Vec<Entry> {
+        let notional = quantity.abs() * price;
+        let mut entries = vec![Entry::new(timestamp, Cost::Fee, -notional * self.fees.rate(liquidity))];
+        if liquidity == Liquidity::Taker && self.slippage != Slippage::Zero {
+            entries.push(Entry::new(timestamp, Cost::Slippage, -notional * self.slippage.rate(quantity, volume, spread)));
+        }
+        entries
+    }
+
+    // longs pay shorts on a positive rate, `position` is signed
+    pub fn funding(&self, timestamp: i64, rate: f64, position: f64, mark: f64) -> Entry {
+        Entry::new(timestamp, Cost::Funding, -position * mark * rate)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cost {
+    Fee,
+    Slippage,
+    Funding,
+    Liquidation, // clearance fee, the maintenance margin left at a forced liquidation
+}
+
+impl Cost {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cost::Fee => "Fee",
+            Cost::Slippage => "Slippage",
+            Cost::Funding => "Funding",
+            Cost::Liquidation => "Liquidation",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    pub timestamp: i64,
+    pub cost: Cost,
+    pub amount: f64, // cash flow to the account, negative when paid
+}
+
+impl Entry {
+    pub fn new(timestamp: i64, cost: Cost, amount: f64) -> Self {
+        Self { timestamp, cost, amount }
+    }
+}
+
+pub fn total(ledger: &[Entry], cost: Cost) -> f64 {
+    ledger.iter().filter(|e| e.cost == cost).map(|e| e.amount).sum()
+}
+
+pub fn ledger_to_frame(ledger: &[Entry]) -> Result<DataFrame> {
+    DataFrame::new(vec![
+        timestamp_series("timestamp", ledger.iter().map(|e| e.timestamp).collect())?,
+        Series::new("cost", ledger.iter().map(|e| e.cost.name()).collect::<Vec<_>>()).cast(&DataType::Categorical(None))?,
+        Series::new("amount", ledger.iter().map(|e| e.amount).collect::<Vec<_>>()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fees_and_slippage() {
+        assert_eq!(Fees::for_volume(0.0).tier, FEE_TIERS[0]);
+        assert_eq!(Fees::for_volume(70e6).tier, FEE_TIERS[2]);
+        assert!((Fees::vip(0).with_bnb().rate(Liquidity::Taker) - 0.00036).abs() < 1e-12);
+
+        assert!((Slippage::Fixed(2.0).rate(1.0, 10.0, None) - 0.0002).abs() < 1e-12);
+        assert!((Slippage::Spread("spread".into()).rate(1.0, 10.0, Some(0.001)) - 0.0005).abs() < 1e-12);
+        assert!((Slippage::Participation { impact: 0.01 }.rate(-4.0, 100.0, None) - 0.002).abs() < 1e-12);
+
+        let model = CostModel::new(Fees::vip(0), Slippage::Fixed(1.0));
+        let entries = model.fill(0, Liquidity::Taker, 2.0, 100.0, 10.0, None);
+        assert_eq!(entries.len(), 2);
+        assert!((total(&entries, Cost::Fee) + 0.08).abs() < 1e-12);
+        assert!((total(&entries, Cost::Slippage) + 0.02).abs() < 1e-12);
+        assert_eq!(model.fill(0, Liquidity::Maker, 2.0, 100.0, 10.0, None).len(), 1);
+    }
+
+    #[test]
+    fn test_funding() {
+        let hour = 3_600_000i64;
+        let funding = FundingRates::from_frame(&df![
+            "fundingTime" => [16 * hour, 0, 8 * hour],
+            "fundingRate" => [0.0003, 0.0001, -0.0002],
+        ].unwrap())
+        .unwrap();
+        // 4h bars: fundings are settled at the open of the bars starting at 0h, 8h and 16h
+        let bars = (0..6).map(|i| i * 4 * hour).collect::<Vec<_>>();
+        let per_bar = funding.per_bar(&bars);
+        assert_eq!(per_bar.iter().map(|f| f.len()).collect::<Vec<_>>(), vec![1, 0, 1, 0, 1, 0]);
+        assert_eq!(per_bar[2], vec![(8 * hour, -0.0002)]);
+
+        let model = CostModel::default().with_funding(funding);
+        assert!((model.funding(0, 0.0001, 2.0, 100.0).amount + 0.02).abs() < 1e-12);
+        assert!((model.funding(0, 0.0001, -2.0, 100.0).amount - 0.02).abs() < 1e-12);
+    }
+}
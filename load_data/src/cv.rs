@@ -0,0 +1,141 @@
+use polars::prelude::*;
+
+// Cross-validation splits for labeled events whose outcomes span several bars, e.g. from the start of a
+// group to the touch of its barrier. Training events overlapping a test fold are purged, and the ones
+// starting within `embargo` after it are dropped too, since their features saw the test outcomes.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: i64,
+    pub end: i64, // inclusive, when the outcome is known
+}
+
+// spans from two timestamp columns, e.g. `timestamp` and `touch timestamp` of `labels_to_frame`
+pub fn spans_from_frame(df: &DataFrame, start: &str, end: &str) -> Result<Vec<Span>> {
+    let column = |name: &str| -> Result<Vec<i64>> {
+        let s = df.column(name)?.cast(&DataType::Int64)?;
+        Ok(s.i64()?.into_no_null_iter().collect())
+    };
+    Ok(column(start)?.into_iter().zip(column(end)?).map(|(start, end)| Span { start, end }).collect())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Split {
+    pub train: Vec<usize>, // indices into the events
+    pub test: Vec<usize>,
+    pub groups: Vec<usize>, // the groups of events tested
+}
+
+// events in the order of their start, cut into `n` contiguous groups
+fn groups(spans: &[Span], n: usize) -> Vec<Vec<usize>> {
+    let mut order = (0..spans.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| (spans[*i].start, *i));
+    let n = n.clamp(1, spans.len().max(1));
+    (0..n).map(|g| order[g * spans.len() / n..(g + 1) * spans.len() / n].to_vec()).collect()
+}
+
+fn split(spans: &[Span], groups: &[Vec<usize>], tested: &[usize], embargo: i64) -> Split {
+    // time range of each tested group, the embargo extends it past its last outcome
+    let ranges = tested
+        .iter()
+        .map(|g| {
+            let start = groups[*g].iter().map(|i| spans[*i].start).min().unwrap_or(i64::MAX);
+            let end = groups[*g].iter().map(|i| spans[*i].end).max().unwrap_or(i64::MIN);
+            (start, end)
+        })
+        .collect::<Vec<_>>();
+    let leaks = |span: &Span| ranges.iter().any(|(start, end)| span.start <= end + embargo && span.end >= *start);
+
+    let mut test = tested.iter().flat_map(|g| groups[*g].iter().copied()).collect::<Vec<_>>();
+    test.sort_unstable();
+    let mut train = (0..groups.len())
+        .filter(|g| !tested.contains(g))
+        .flat_map(|g| groups[g].iter().copied())
+        .filter(|i| !leaks(&spans[*i]))
+        .collect::<Vec<_>>();
+    train.sort_unstable();
+    Split { train, test, groups: tested.to_vec() }
+}
+
+// k contiguous folds, each tested once
+pub fn purged_kfold(spans: &[Span], k: usize, embargo: i64) -> Vec<Split> {
+    let groups = groups(spans, k);
+    (0..groups.len()).map(|g| split(spans, &groups, &[g], embargo)).collect()
+}
+
+pub(crate) fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![Vec::new()];
+    }
+    if n < k {
+        return Vec::new();
+    }
+    // the ones with the last element and the ones without
+    let mut with = combinations(n - 1, k - 1);
+    with.iter_mut().for_each(|c| c.push(n - 1));
+    let mut combinations = combinations(n - 1, k);
+    combinations.extend(with);
+    combinations.sort();
+    combinations
+}
+
+// Combinatorial purged cross-validation: every choice of `k` test groups out of `n`.
+pub fn combinatorial_purged(spans: &[Span], n: usize, k: usize, embargo: i64) -> Vec<Split> {
+    let groups = groups(spans, n);
+    combinations(groups.len(), k).iter().map(|tested| split(spans, &groups, tested, embargo)).collect()
+}
+
+// Backtest paths of combinatorial splits: each group is tested by k of the C(n, k) splits, so there are
+// C(n - 1, k - 1) paths, each taking every group from a different split. The split of each group by path.
+pub fn backtest_paths(splits: &[Split], n: usize) -> Vec<Vec<usize>> {
+    let mut by_group = vec![Vec::new(); n];
+    for (s, split) in splits.iter().enumerate() {
+        for g in &split.groups {
+            by_group[*g].push(s);
+        }
+    }
+    let paths = by_group.iter().map(|s| s.len()).min().unwrap_or(0);
+    (0..paths).map(|p| by_group.iter().map(|s| s[p]).collect()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ten events of 3 ms every 2 ms, each overlapping the next one
+    fn spans() -> Vec<Span> {
+        (0..10).map(|i| Span { start: 2 * i, end: 2 * i + 3 }).collect()
+    }
+
+    #[test]
+    fn test_purged_kfold() {
+        let splits = purged_kfold(&spans(), 5, 0);
+        assert_eq!(splits.len(), 5);
+        assert_eq!(splits[2].test, vec![4, 5]);
+        // 3 ends at 9 after 4 starts at 8, and 6 starts at 12 before 5 ends at 13
+        assert_eq!(splits[2].train, vec![0, 1, 2, 7, 8, 9]);
+        // the embargo drops 7 starting at 14 as well
+        assert_eq!(purged_kfold(&spans(), 5, 1).into_iter().nth(2).unwrap().train, vec![0, 1, 2, 8, 9]);
+        // no test event is ever trained on
+        for split in &splits {
+            assert!(split.train.iter().all(|i| !split.test.contains(i)));
+        }
+    }
+
+    #[test]
+    fn test_combinatorial() {
+        let splits = combinatorial_purged(&spans(), 5, 2, 0);
+        assert_eq!(splits.len(), 10);
+        assert_eq!(splits[0].groups, vec![0, 1]);
+        assert_eq!(splits[0].test, vec![0, 1, 2, 3]);
+        assert_eq!(splits[0].train, vec![5, 6, 7, 8, 9]);
+
+        let paths = backtest_paths(&splits, 5);
+        assert_eq!(paths.len(), 4);
+        for path in &paths {
+            for (group, s) in path.iter().enumerate() {
+                assert!(splits[*s].groups.contains(&group));
+            }
+        }
+    }
+}
@@ -36,6 +36,12 @@ pub async fn download_montly_candles(start: &str, end: Option<&str>, interval: &
 }
 
 pub fn aggregate(lf: LazyFrame, sigma: f64, target_pnl: f64, duration: i64) -> LazyFrame
+{
+    signals(rolling_stats(lf, duration), sigma, target_pnl)
+}
+
+// the first stage of `aggregate`, only depends on the duration so it can be cached across sigmas
+pub fn rolling_stats(lf: LazyFrame, duration: i64) -> LazyFrame
 {
     let rolling_option = RollingOptions {
         window_size: Duration::new(duration),
@@ -56,7 +62,12 @@ pub fn aggregate(lf: LazyFrame, sigma: f64, target_pnl: f64, duration: i64) -> L
         mean_low(&rolling_option),
         std_low(&rolling_option),
     ])
-    .with_columns([
+}
+
+// the rest of `aggregate` over the output of `rolling_stats`
+pub fn signals(lf: LazyFrame, sigma: f64, target_pnl: f64) -> LazyFrame
+{
+    lf.with_columns([
         abnormal(sigma),
         upper_bound_touched(sigma),
         lower_bound_touched(sigma),
@@ -90,6 +101,67 @@ pub fn read_csvs<'a, I, T>(files: I) -> Result<LazyFrame>
     concat(query, true)
 }
 
+// Candle columns as plain vectors for the bar by bar loops, timestamps in milliseconds.
+#[derive(Debug, Clone, Default)]
+pub struct Candles {
+    pub timestamp: Vec<i64>,
+    pub open: Vec<f64>,
+    pub high: Vec<f64>,
+    pub low: Vec<f64>,
+    pub close: Vec<f64>,
+    pub volume: Vec<f64>,
+}
+
+impl Candles {
+    pub fn from_frame(df: &DataFrame) -> Result<Self> {
+        let timestamp = df.column("timestamp")?.cast(&DataType::Int64)?;
+        Ok(Self {
+            timestamp: timestamp.i64()?.into_no_null_iter().collect(),
+            open: f64_column(df, "open")?,
+            high: f64_column(df, "high")?,
+            low: f64_column(df, "low")?,
+            close: f64_column(df, "close")?,
+            volume: f64_column(df, "volume")?,
+        })
+    }
+
+    // trades with `timestamp`, `price` and `qty` as candles of a single trade each
+    pub fn from_trades(df: &DataFrame) -> Result<Self> {
+        let timestamp = df.column("timestamp")?.cast(&DataType::Int64)?;
+        let price = f64_column(df, "price")?;
+        Ok(Self {
+            timestamp: timestamp.i64()?.into_no_null_iter().collect(),
+            open: price.clone(),
+            high: price.clone(),
+            low: price.clone(),
+            close: price,
+            volume: f64_column(df, "qty")?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.timestamp.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timestamp.is_empty()
+    }
+}
+
+// a numeric column as f64 with nulls as NaN
+pub fn f64_column(df: &DataFrame, name: &str) -> Result<Vec<f64>> {
+    let s = df.column(name)?.cast(&DataType::Float64)?;
+    Ok(s.f64()?.into_iter().map(|v| v.unwrap_or(f64::NAN)).collect())
+}
+
+pub fn bool_column(df: &DataFrame, name: &str) -> Result<Vec<bool>> {
+    Ok(df.column(name)?.bool()?.into_iter().map(|v| v.unwrap_or(false)).collect())
+}
+
+pub fn timestamp_series(name: &str, timestamps: Vec<i64>) -> Result<Series> {
+    Series::new(name, timestamps).cast(&DataType::Datetime(TimeUnit::Milliseconds, Some("UTC".into())))
+}
+
 fn timestamp() -> Expr {
     col("openTime")
         .cast(
@@ -155,13 +227,31 @@ fn lower_bound_touched(sigma: f64) -> Expr {
     ).alias("lower band touched")
 }
 
-fn group() -> Expr {
+// the primary signal, a group starts at each of these events
+pub fn signal() -> Expr {
     col("abnormal volume").and(col("upper band touched").xor(col("lower band touched")))
+}
+
+fn group() -> Expr {
+    signal()
     .cumsum(false)
     // .forward_fill(None)
     .alias("group")
 }
 
+// columns of `aggregate` computed over a whole group, so they see bars after the current one
+pub const LOOK_AHEAD_COLUMNS: [&str; 9] = [
+    "period",
+    "max high for duration",
+    "offset to max high",
+    "min low for duration",
+    "offset to min low",
+    "rising float",
+    "falling float",
+    "trend from base",
+    "trend_forcast_over_group",
+];
+
 fn stat_over_group() -> Vec<Expr> {
     vec![
         count().over([col("group")]).alias("period"),
@@ -174,32 +264,217 @@ fn stat_over_group() -> Vec<Expr> {
     ]
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trend {
+    Unknown,
+    Bear,
+    Bull,
+    Chaos,
+}
+
+impl Trend {
+    pub const ALL: [Trend; 4] = [Trend::Unknown, Trend::Bear, Trend::Bull, Trend::Chaos];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Trend::Unknown => "Unknown",
+            Trend::Bear => "Bear",
+            Trend::Bull => "Bull",
+            Trend::Chaos => "Chaos",
+        }
+    }
+
+    // `trend from base` used to be stored as these u32 codes
+    pub fn code(&self) -> u32 {
+        match self {
+            Trend::Unknown => 0,
+            Trend::Bear => 1,
+            Trend::Bull => 2,
+            Trend::Chaos => 3,
+        }
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Trend::ALL.iter().copied().find(|trend| trend.code() == code)
+    }
+
+    pub fn lit(&self) -> Expr {
+        lit(self.name())
+    }
+
+    pub fn dtype() -> DataType {
+        DataType::Categorical(None)
+    }
+
+    // reads a trend column stored as categorical, utf8 or the legacy codes, of any integer type as
+    // a csv reads them back as i64
+    pub fn from_series(series: &Series) -> Result<Vec<Option<Trend>>> {
+        match series.dtype() {
+            DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64 => {
+                let codes = series.cast(&DataType::UInt32)?;
+                Ok(codes.u32()?.into_iter().map(|code| code.and_then(Trend::from_code)).collect())
+            }
+            _ => series
+                .cast(&DataType::Utf8)?
+                .utf8()?
+                .into_iter()
+                .map(|name| name.map(str::parse).transpose())
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for Trend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Trend {
+    type Err = PolarsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Trend::ALL
+            .iter()
+            .copied()
+            .find(|trend| trend.name() == s)
+            .ok_or_else(|| PolarsError::ComputeError(format!("unknown trend: {}", s).into()))
+    }
+}
+
+// Kline intervals of the futures api
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interval {
+    M1,
+    M3,
+    M5,
+    M15,
+    M30,
+    H1,
+    H2,
+    H4,
+    H6,
+    H8,
+    H12,
+    D1,
+    D3,
+    W1,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 14] = [
+        Interval::M1,
+        Interval::M3,
+        Interval::M5,
+        Interval::M15,
+        Interval::M30,
+        Interval::H1,
+        Interval::H2,
+        Interval::H4,
+        Interval::H6,
+        Interval::H8,
+        Interval::H12,
+        Interval::D1,
+        Interval::D3,
+        Interval::W1,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interval::M1 => "1m",
+            Interval::M3 => "3m",
+            Interval::M5 => "5m",
+            Interval::M15 => "15m",
+            Interval::M30 => "30m",
+            Interval::H1 => "1h",
+            Interval::H2 => "2h",
+            Interval::H4 => "4h",
+            Interval::H6 => "6h",
+            Interval::H8 => "8h",
+            Interval::H12 => "12h",
+            Interval::D1 => "1d",
+            Interval::D3 => "3d",
+            Interval::W1 => "1w",
+        }
+    }
+
+    pub fn minutes(&self) -> i64 {
+        match self {
+            Interval::M1 => 1,
+            Interval::M3 => 3,
+            Interval::M5 => 5,
+            Interval::M15 => 15,
+            Interval::M30 => 30,
+            Interval::H1 => 60,
+            Interval::H2 => 2 * 60,
+            Interval::H4 => 4 * 60,
+            Interval::H6 => 6 * 60,
+            Interval::H8 => 8 * 60,
+            Interval::H12 => 12 * 60,
+            Interval::D1 => 24 * 60,
+            Interval::D3 => 3 * 24 * 60,
+            Interval::W1 => 7 * 24 * 60,
+        }
+    }
+
+    pub fn millis(&self) -> i64 {
+        self.minutes() * 60_000
+    }
+
+    // crypto trades around the clock, 365 days a year
+    pub fn periods_per_year(&self) -> f64 {
+        365f64 * 24f64 * 60f64 / self.minutes() as f64
+    }
+}
+
+impl std::fmt::Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Interval {
+    type Err = PolarsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Interval::ALL
+            .iter()
+            .copied()
+            .find(|interval| interval.name() == s)
+            .ok_or_else(|| PolarsError::ComputeError(format!("unknown interval: {}", s).into()))
+    }
+}
+
 fn trend_from_base(target_pnl: f64) -> Expr {
     when(
         col("rising float").gt_eq(lit(target_pnl)).and(col("falling float").lt_eq(lit(-target_pnl)))
     ).then(
-        lit(3u32) // "Chaos"
+        Trend::Chaos.lit()
     ).when(
         col("rising float").gt_eq(lit(target_pnl))
     ).then(
-        lit(2u32) // "Bull"
+        Trend::Bull.lit()
     ).when(
         col("falling float").lt_eq(lit(-target_pnl))
     ).then(
-        lit(1u32) // "Bear"
+        Trend::Bear.lit()
     ).otherwise(
-        lit(0u32) // "Unknown"
-    ).alias("trend from base")
+        Trend::Unknown.lit()
+    )
+    .cast(Trend::dtype())
+    .alias("trend from base")
 }
 
 fn trend_forcast_over_group() -> Expr {
     col("trend from base").filter(
-        col("trend from base").eq(lit(3u32))
-        .or(
-            col("trend from base").eq(lit(2u32))
-        ).or(
-            col("trend from base").eq(lit(1u32))
-        )
+        col("trend from base").neq(Trend::Unknown.lit())
     ).first()
     .over([col("group")])
     .alias("trend_forcast_over_group")
@@ -208,6 +483,19 @@ fn trend_forcast_over_group() -> Expr {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_trend_from_series() {
+        let expected = Trend::ALL.iter().map(|trend| Some(*trend)).chain([None]).collect::<Vec<_>>();
+        let codes = Trend::ALL.iter().map(|trend| Some(trend.code())).chain([None]).collect::<Vec<_>>();
+        let legacy = Series::new("trend", codes);
+        for dtype in [DataType::UInt32, DataType::Int64, DataType::Int32] {
+            assert_eq!(Trend::from_series(&legacy.cast(&dtype).unwrap()).unwrap(), expected, "{:?}", dtype);
+        }
+        let names = Series::new("trend", Trend::ALL.iter().map(|trend| Some(trend.name())).chain([None]).collect::<Vec<_>>());
+        assert_eq!(Trend::from_series(&names).unwrap(), expected);
+        assert_eq!(Trend::from_series(&names.cast(&Trend::dtype()).unwrap()).unwrap(), expected);
+    }
+
     #[test]
     fn test_aggregate() {
         let files = vec![
@@ -223,4 +511,30 @@ mod tests {
 
         write_csv(table, "make-list.csv");
     }
+
+    #[test]
+    fn test_trend_from_base() {
+        let df = df![
+            "group" => [1u32, 1, 1, 2, 2, 3],
+            "rising float" => [0.0f64, 0.02, 0.03, 0.0, 0.02, 0.0],
+            "falling float" => [0.0f64, 0.0, -0.02, -0.02, -0.02, 0.0],
+        ].unwrap();
+        let df = df.lazy()
+            .with_column(trend_from_base(0.01))
+            .with_column(trend_forcast_over_group())
+            .collect()
+            .unwrap();
+
+        use Trend::*;
+        assert_eq!(
+            Trend::from_series(df.column("trend from base").unwrap()).unwrap(),
+            vec![Some(Unknown), Some(Bull), Some(Chaos), Some(Bear), Some(Chaos), Some(Unknown)]
+        );
+        assert_eq!(
+            Trend::from_series(df.column("trend_forcast_over_group").unwrap()).unwrap(),
+            vec![Some(Bull), Some(Bull), Some(Bull), Some(Bear), Some(Bear), None]
+        );
+        assert_eq!("Chaos".parse::<Trend>().unwrap(), Chaos);
+        assert_eq!(Trend::from_code(Bear.code()), Some(Bear));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,252 @@
+use polars::prelude::*;
+use std::time::Instant;
+
+use crate::pattern::{find_similar_with, forward_return_name, Matcher, PatternSpec, WeightedCosine, Windows};
+
+// Dynamic time warping over flattened windows of `features` values per tick,
+// with the squared euclidean cost between ticks and a Sakoe-Chiba band of `band` ticks.
+#[derive(Debug, Clone, Copy)]
+pub struct Dtw {
+    pub features: usize,
+    pub band: usize,
+}
+
+pub struct DtwQuery {
+    window: Vec<f64>,
+    upper: Vec<f64>,
+    lower: Vec<f64>,
+}
+
+impl Dtw {
+    pub fn new(spec: &PatternSpec, band: usize) -> Self {
+        Self { features: spec.features.len(), band }
+    }
+
+    fn ticks(&self, window: &[f64]) -> usize {
+        window.len() / self.features
+    }
+
+    fn cost(&self, a: &[f64], i: usize, b: &[f64], j: usize) -> f64 {
+        let f = self.features;
+        a[i * f..(i + 1) * f].iter().zip(&b[j * f..(j + 1) * f]).map(|(x, y)| (x - y) * (x - y)).sum()
+    }
+
+    // upper and lower envelopes of the window within the band, for LB_Keogh
+    pub fn envelope(&self, window: &[f64]) -> (Vec<f64>, Vec<f64>) {
+        let (n, f) = (self.ticks(window), self.features);
+        let mut upper = vec![f64::NEG_INFINITY; window.len()];
+        let mut lower = vec![f64::INFINITY; window.len()];
+        for j in 0..n {
+            for i in j.saturating_sub(self.band)..(j + self.band + 1).min(n) {
+                for k in 0..f {
+                    upper[j * f + k] = upper[j * f + k].max(window[i * f + k]);
+                    lower[j * f + k] = lower[j * f + k].min(window[i * f + k]);
+                }
+            }
+        }
+        (upper, lower)
+    }
+
+    // LB_Keogh of a candidate against the query envelope, abandoned once above `best`
+    pub fn lb_keogh(&self, upper: &[f64], lower: &[f64], candidate: &[f64], best: f64) -> f64 {
+        let mut bound = 0f64;
+        for ((c, u), l) in candidate.iter().zip(upper).zip(lower) {
+            if c > u {
+                bound += (c - u) * (c - u);
+            } else if c < l {
+                bound += (c - l) * (c - l);
+            }
+            if bound > best {
+                break;
+            }
+        }
+        bound
+    }
+
+    // DTW distance abandoned with None once every warping path exceeds `best`
+    pub fn distance(&self, a: &[f64], b: &[f64], best: f64) -> Option<f64> {
+        let n = self.ticks(a);
+        let m = self.ticks(b);
+        let mut previous = vec![f64::INFINITY; m + 1];
+        let mut current = vec![f64::INFINITY; m + 1];
+        previous[0] = 0f64;
+
+        for i in 1..=n {
+            current.iter_mut().for_each(|d| *d = f64::INFINITY);
+            let from = i.saturating_sub(self.band).max(1);
+            let to = (i + self.band).min(m);
+            let mut row_min = f64::INFINITY;
+            for j in from..=to {
+                let d = self.cost(a, i - 1, b, j - 1) + previous[j - 1].min(previous[j]).min(current[j - 1]);
+                current[j] = d;
+                row_min = row_min.min(d);
+            }
+            if row_min > best {
+                return None;
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+        let d = previous[m];
+        if d.is_finite() {
+            Some(d)
+        } else {
+            None
+        }
+    }
+}
+
+impl Matcher for Dtw {
+    type Query = DtwQuery;
+
+    fn prepare(&self, window: Vec<f64>) -> Self::Query {
+        let (upper, lower) = self.envelope(&window);
+        DtwQuery { window, upper, lower }
+    }
+
+    // the negated distance, so that closer windows rank higher
+    fn similarity(&self, query: &Self::Query, candidate: &[f64], threshold: f64) -> Option<f64> {
+        let best = -threshold;
+        if self.lb_keogh(&query.upper, &query.lower, candidate, best) > best {
+            return None;
+        }
+        self.distance(&query.window, candidate, best).map(|d| -d)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatcherReport {
+    pub name: String,
+    pub elapsed_ms: f64,
+    pub queries: usize,
+    pub mae: Vec<f64>,      // per horizon of the spec, forecast as the mean forward return of the matches
+    pub hit_rate: Vec<f64>, // per horizon, the share of forecasts with the right sign
+}
+
+// Forecasts every query by its neighbours and scores them against what actually happened.
+pub fn neighbour_forecast<M: Matcher>(
+    name: &str,
+    matcher: &M,
+    df: &DataFrame,
+    spec: &PatternSpec,
+    queries: &[usize],
+) -> Result<MatcherReport> {
+    let windows = Windows::new(df, spec)?;
+    let close = df.column("close")?.cast(&DataType::Float64)?;
+    let close = close.f64()?;
+    let forward = |index: usize, horizon: usize| match (close.get(index), close.get(index + horizon)) {
+        (Some(base), Some(after)) => Some(after / base - 1f64),
+        _ => None,
+    };
+
+    let started = Instant::now();
+    let matches = queries.iter().map(|&q| find_similar_with(matcher, &windows, spec, q)).collect::<Vec<_>>();
+    let elapsed_ms = started.elapsed().as_secs_f64() * 1e3;
+
+    let mut mae = Vec::with_capacity(spec.horizons.len());
+    let mut hit_rate = Vec::with_capacity(spec.horizons.len());
+    for &horizon in &spec.horizons {
+        let (mut errors, mut hits, mut count) = (0f64, 0usize, 0usize);
+        for (query, matches) in queries.iter().zip(&matches) {
+            let returns = matches.iter().filter_map(|m| forward(m.index, horizon)).collect::<Vec<_>>();
+            if let (Some(actual), false) = (forward(*query, horizon), returns.is_empty()) {
+                let forecast = returns.iter().sum::<f64>() / returns.len() as f64;
+                errors += (forecast - actual).abs();
+                hits += (forecast.signum() == actual.signum()) as usize;
+                count += 1;
+            }
+        }
+        mae.push(if count > 0 { errors / count as f64 } else { f64::NAN });
+        hit_rate.push(if count > 0 { hits as f64 / count as f64 } else { f64::NAN });
+    }
+
+    Ok(MatcherReport { name: name.to_string(), elapsed_ms, queries: queries.len(), mae, hit_rate })
+}
+
+// Weighted cosine against DTW on the same queries, one row per matcher.
+pub fn compare_matchers(df: &DataFrame, spec: &PatternSpec, band: usize, queries: &[usize]) -> Result<DataFrame> {
+    let reports = vec![
+        neighbour_forecast("weighted cosine", &WeightedCosine::new(spec), df, spec, queries)?,
+        neighbour_forecast("dtw", &Dtw::new(spec, band), df, spec, queries)?,
+    ];
+    reports_to_frame(spec, &reports)
+}
+
+pub fn reports_to_frame(spec: &PatternSpec, reports: &[MatcherReport]) -> Result<DataFrame> {
+    let mut columns = vec![
+        Series::new("matcher", reports.iter().map(|r| r.name.as_str()).collect::<Vec<_>>()),
+        Series::new("elapsed ms", reports.iter().map(|r| r.elapsed_ms).collect::<Vec<_>>()),
+        Series::new("queries", reports.iter().map(|r| r.queries as u32).collect::<Vec<_>>()),
+    ];
+    for (i, &horizon) in spec.horizons.iter().enumerate() {
+        let name = forward_return_name(horizon);
+        columns.push(Series::new(&format!("mae {}", name), reports.iter().map(|r| r.mae[i]).collect::<Vec<_>>()));
+        columns.push(Series::new(&format!("hit rate {}", name), reports.iter().map(|r| r.hit_rate[i]).collect::<Vec<_>>()));
+    }
+    DataFrame::new(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::mean_scaling;
+
+    fn naive(a: &[f64], b: &[f64], band: usize) -> f64 {
+        let (n, m) = (a.len(), b.len());
+        let mut d = vec![vec![f64::INFINITY; m + 1]; n + 1];
+        d[0][0] = 0f64;
+        for i in 1..=n {
+            for j in 1..=m {
+                if i.abs_diff(j) <= band {
+                    d[i][j] = (a[i - 1] - b[j - 1]).powi(2) + d[i - 1][j - 1].min(d[i - 1][j]).min(d[i][j - 1]);
+                }
+            }
+        }
+        d[n][m]
+    }
+
+    #[test]
+    fn test_distance_and_lower_bound() {
+        let dtw = Dtw { features: 1, band: 2 };
+        let a = [0f64, 1.0, 2.0, 3.0, 2.0, 1.0, 0.0, -1.0];
+        let b = [0f64, 0.0, 1.0, 2.0, 3.0, 2.0, 1.0, 0.0];
+        let d = dtw.distance(&a, &b, f64::INFINITY).unwrap();
+        assert!((d - naive(&a, &b, 2)).abs() < 1e-12);
+        // a shifted copy is much closer under warping than point by point
+        let euclidean = a.iter().zip(&b).map(|(x, y)| (x - y).powi(2)).sum::<f64>();
+        assert!(d < euclidean);
+
+        let (upper, lower) = dtw.envelope(&a);
+        assert!(dtw.lb_keogh(&upper, &lower, &b, f64::INFINITY) <= d);
+        assert_eq!(dtw.distance(&a, &b, d / 2f64), None);
+    }
+
+    #[test]
+    fn test_compare_matchers() {
+        let height = 240;
+        let close = (0..height).map(|i| 100f64 + (i as f64 / 6f64).sin() + (i as f64 / 17f64).cos()).collect::<Vec<_>>();
+        let df = df![
+            "timestamp" => (0..height as i64).collect::<Vec<_>>(),
+            "close" => close,
+        ].unwrap();
+        let spec = PatternSpec { window: 10, top_k: 4, features: vec!["close".into()], horizons: vec![1, 3], ..Default::default() };
+        let df = mean_scaling(df.lazy(), &spec).collect().unwrap();
+        let queries = (150..230).collect::<Vec<_>>();
+
+        let table = compare_matchers(&df, &spec, 2, &queries).unwrap();
+        assert_eq!(table.height(), 2);
+        assert_eq!(table.width(), 3 + 2 * spec.horizons.len());
+
+        // pruning must not change which windows DTW picks
+        let windows = Windows::new(&df, &spec).unwrap();
+        let dtw = Dtw::new(&spec, 2);
+        let query = windows.get(200).unwrap();
+        let found = find_similar_with(&dtw, &windows, &spec, 200);
+        let mut exact = (0..=200 - spec.exclusion())
+            .filter_map(|i| windows.get(i).map(|w| dtw.distance(&query, &w, f64::INFINITY).unwrap()))
+            .collect::<Vec<_>>();
+        exact.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (m, d) in found.iter().zip(&exact) {
+            assert!((-m.similarity - d).abs() < 1e-9);
+        }
+    }
+}
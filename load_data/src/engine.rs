@@ -0,0 +1,640 @@
+use polars::prelude::*;
+
+use crate::costs::{Cost, CostModel, Entry};
+use crate::data::{timestamp_series, Candles, LOOK_AHEAD_COLUMNS};
+use crate::margin::{liquidated, MarginSpec};
+
+// Event-driven backtest: candles (or trades as one-price candles, see `Candles::from_trades`) are
+// replayed bar by bar through a `Strategy`. The strategy sees a bar once it has closed and its orders
+// work from the next bar on, filled conservatively against OHLC.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn sign(&self) -> f64 {
+        match self {
+            Side::Buy => 1f64,
+            Side::Sell => -1f64,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Side::Buy => "Buy",
+            Side::Sell => "Sell",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderKind {
+    Market,
+    Limit(f64),
+    Stop(f64),
+    StopLimit { stop: f64, limit: f64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Order {
+    pub id: u64, // assigned on submission
+    pub side: Side,
+    pub quantity: f64,
+    pub kind: OrderKind,
+    pub reduce_only: bool,
+    pub post_only: bool,
+    pub(crate) arrived: bool,   // has seen a bar since the submission
+    pub(crate) triggered: bool, // a stop limit whose stop was hit rests as a limit
+}
+
+impl Order {
+    pub fn new(side: Side, quantity: f64, kind: OrderKind) -> Self {
+        Self { id: 0, side, quantity, kind, reduce_only: false, post_only: false, arrived: false, triggered: false }
+    }
+
+    pub fn market(side: Side, quantity: f64) -> Self {
+        Self::new(side, quantity, OrderKind::Market)
+    }
+
+    pub fn limit(side: Side, quantity: f64, price: f64) -> Self {
+        Self::new(side, quantity, OrderKind::Limit(price))
+    }
+
+    pub fn stop(side: Side, quantity: f64, stop: f64) -> Self {
+        Self::new(side, quantity, OrderKind::Stop(stop))
+    }
+
+    pub fn stop_limit(side: Side, quantity: f64, stop: f64, limit: f64) -> Self {
+        Self::new(side, quantity, OrderKind::StopLimit { stop, limit })
+    }
+
+    pub fn reduce_only(self) -> Self {
+        Self { reduce_only: true, ..self }
+    }
+
+    pub fn post_only(self) -> Self {
+        Self { post_only: true, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+impl Liquidity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Liquidity::Maker => "Maker",
+            Liquidity::Taker => "Taker",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub order: u64, // 0 for a forced liquidation
+    pub bar: usize,
+    pub timestamp: i64,
+    pub side: Side,
+    pub quantity: f64,
+    pub price: f64,
+    pub liquidity: Liquidity,
+}
+
+// Numeric columns of the replayed frame besides the candle itself, booleans as 0 and 1.
+#[derive(Debug, Clone, Default)]
+pub struct Features {
+    names: Vec<String>,
+    values: Vec<Vec<f64>>,
+}
+
+impl Features {
+    // every numeric or boolean column but the candle and `LOOK_AHEAD_COLUMNS`
+    pub fn from_frame(df: &DataFrame) -> Result<Self> {
+        let skip = ["timestamp", "openTime", "open", "high", "low", "close", "volume"];
+        let mut features = Features::default();
+        for s in df.get_columns() {
+            if skip.contains(&s.name()) || LOOK_AHEAD_COLUMNS.contains(&s.name()) {
+                continue;
+            }
+            let values = match s.dtype() {
+                DataType::Boolean => s.bool()?.into_iter().map(|v| v.map(|b| b as u8 as f64).unwrap_or(f64::NAN)).collect(),
+                dtype if dtype.is_numeric() => {
+                    s.cast(&DataType::Float64)?.f64()?.into_iter().map(|v| v.unwrap_or(f64::NAN)).collect()
+                }
+                _ => continue,
+            };
+            features.names.push(s.name().to_string());
+            features.values.push(values);
+        }
+        Ok(features)
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+}
+
+pub struct Bar<'a> {
+    pub index: usize,
+    pub timestamp: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub(crate) features: &'a Features,
+    pub(crate) row: usize, // of the features, the index but for a live bar
+}
+
+impl Bar<'_> {
+    pub fn feature(&self, name: &str) -> Option<f64> {
+        let at = self.features.names.iter().position(|n| n == name)?;
+        Some(self.features.values[at][self.row])
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Account {
+    pub capital: f64,
+    pub position: f64, // signed quantity
+    pub entry_price: f64,
+    pub realized: f64,
+    pub costs: f64, // fees, slippage and funding paid
+}
+
+impl Account {
+    pub fn new(capital: f64) -> Self {
+        Self { capital, ..Default::default() }
+    }
+
+    pub fn apply(&mut self, side: Side, quantity: f64, price: f64) {
+        let signed = side.sign() * quantity;
+        if self.position == 0f64 || self.position.signum() == signed.signum() {
+            let size = self.position.abs() + quantity;
+            self.entry_price = (self.entry_price * self.position.abs() + price * quantity) / size;
+            self.position += signed;
+            return;
+        }
+        let closing = quantity.min(self.position.abs());
+        self.realized += closing * (price - self.entry_price) * self.position.signum();
+        self.position += signed;
+        if self.position.abs() < 1e-12 {
+            self.position = 0f64;
+            self.entry_price = 0f64;
+        } else if quantity > closing {
+            // flipped through zero
+            self.entry_price = price;
+        }
+    }
+
+    pub fn unrealized(&self, mark: f64) -> f64 {
+        self.position * (mark - self.entry_price)
+    }
+
+    // balance without the unrealized pnl
+    pub fn wallet(&self) -> f64 {
+        self.capital + self.realized - self.costs
+    }
+
+    pub fn equity(&self, mark: f64) -> f64 {
+        self.wallet() + self.unrealized(mark)
+    }
+
+    pub fn book(&mut self, entry: &Entry) {
+        self.costs -= entry.amount;
+    }
+}
+
+pub struct Context {
+    pub(crate) account: Account,
+    pub(crate) mark: f64,
+    pub(crate) next_id: u64,
+    pub(crate) open_orders: Vec<Order>,
+    submitted: Vec<Order>,
+    cancelled: Vec<u64>,
+}
+
+impl Context {
+    pub(crate) fn new(capital: f64) -> Self {
+        Self {
+            account: Account::new(capital),
+            mark: f64::NAN,
+            next_id: 1,
+            open_orders: Vec::new(),
+            submitted: Vec::new(),
+            cancelled: Vec::new(),
+        }
+    }
+
+    pub fn position(&self) -> f64 {
+        self.account.position
+    }
+
+    pub fn account(&self) -> &Account {
+        &self.account
+    }
+
+    pub fn equity(&self) -> f64 {
+        self.account.equity(self.mark)
+    }
+
+    // orders working on the next bar, the ones submitted on this bar included
+    pub fn open_orders(&self) -> impl Iterator<Item = &Order> {
+        self.open_orders.iter().chain(self.submitted.iter())
+    }
+
+    pub fn submit(&mut self, order: Order) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.submitted.push(Order { id, ..order });
+        id
+    }
+
+    pub fn cancel(&mut self, id: u64) {
+        self.cancelled.push(id);
+    }
+
+    pub fn cancel_all(&mut self) {
+        let ids = self.open_orders().map(|o| o.id).collect::<Vec<_>>();
+        self.cancelled.extend(ids);
+    }
+
+    fn settle_orders(&mut self) {
+        self.open_orders.append(&mut self.submitted);
+        let cancelled = std::mem::take(&mut self.cancelled);
+        self.open_orders.retain(|o| !cancelled.contains(&o.id));
+    }
+}
+
+pub trait Strategy {
+    // called once the bar has closed, orders submitted here work from the next bar
+    fn on_bar(&mut self, bar: &Bar, ctx: &mut Context);
+
+    fn on_fill(&mut self, _fill: &Fill, _ctx: &mut Context) {}
+}
+
+enum Outcome {
+    Rest,
+    Cancel,
+    Fill(f64, Liquidity),
+}
+
+// Fill of a working order against a bar, always assuming the less favourable path within it.
+fn match_order(order: &mut Order, bar: &Bar) -> Outcome {
+    let buy = order.side == Side::Buy;
+    let arriving = !std::mem::replace(&mut order.arrived, true);
+    // at least as good as `level` for the order
+    let marketable = |price: f64, level: f64| if buy { price <= level } else { price >= level };
+    // traded through `level` within the bar, a touch is not enough for a resting order
+    let through = |level: f64| if buy { bar.low < level } else { bar.high > level };
+    let stop_hit = |stop: f64| if buy { bar.high >= stop } else { bar.low <= stop };
+    let worst = |a: f64, b: f64| if buy { a.max(b) } else { a.min(b) };
+
+    let limit = |order: &Order, limit: f64, arriving: bool| {
+        if arriving && marketable(bar.open, limit) {
+            if order.post_only {
+                return Outcome::Cancel;
+            }
+            return Outcome::Fill(bar.open, Liquidity::Taker);
+        }
+        if marketable(bar.open, limit) || through(limit) {
+            return Outcome::Fill(limit, Liquidity::Maker);
+        }
+        Outcome::Rest
+    };
+
+    match order.kind {
+        OrderKind::Market => Outcome::Fill(bar.open, Liquidity::Taker),
+        OrderKind::Limit(price) => limit(order, price, arriving),
+        OrderKind::Stop(stop) if stop_hit(stop) => Outcome::Fill(worst(stop, bar.open), Liquidity::Taker),
+        OrderKind::Stop(_) => Outcome::Rest,
+        OrderKind::StopLimit { limit: price, .. } if order.triggered => limit(order, price, false),
+        OrderKind::StopLimit { stop, limit: price } if stop_hit(stop) => {
+            order.triggered = true;
+            let triggered_at = worst(stop, bar.open);
+            if marketable(triggered_at, price) {
+                Outcome::Fill(triggered_at, Liquidity::Taker)
+            } else {
+                Outcome::Rest
+            }
+        }
+        OrderKind::StopLimit { .. } => Outcome::Rest,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineSpec {
+    pub capital: f64,
+    pub costs: Option<CostModel>,
+    pub margin: Option<MarginSpec>, // unlimited leverage without
+}
+
+impl Default for EngineSpec {
+    fn default() -> Self {
+        Self { capital: 10_000f64, costs: None, margin: None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Liquidation {
+    pub bar: usize,
+    pub timestamp: i64,
+    pub position: f64,
+    pub price: f64,
+}
+
+pub struct Report {
+    pub fills: Vec<Fill>,
+    pub ledger: Vec<Entry>,
+    pub liquidations: Vec<Liquidation>,
+    pub positions: Vec<f64>, // at the close of each bar
+    pub equity: Vec<f64>,
+}
+
+impl Report {
+    pub fn fills_frame(&self) -> Result<DataFrame> {
+        DataFrame::new(vec![
+            timestamp_series("timestamp", self.fills.iter().map(|f| f.timestamp).collect())?,
+            Series::new("order", self.fills.iter().map(|f| f.order).collect::<Vec<_>>()),
+            Series::new("side", self.fills.iter().map(|f| f.side.name()).collect::<Vec<_>>()),
+            Series::new("quantity", self.fills.iter().map(|f| f.quantity).collect::<Vec<_>>()),
+            Series::new("price", self.fills.iter().map(|f| f.price).collect::<Vec<_>>()),
+            Series::new("liquidity", self.fills.iter().map(|f| f.liquidity.name()).collect::<Vec<_>>()),
+        ])
+    }
+
+    pub fn equity_frame(&self, candles: &Candles) -> Result<DataFrame> {
+        DataFrame::new(vec![
+            timestamp_series("timestamp", candles.timestamp.clone())?,
+            Series::new("position", self.positions.clone()),
+            Series::new("equity", self.equity.clone()),
+        ])
+    }
+}
+
+// The state of a run between two bars, stepped over stored candles by `run_candles` or over live
+// candles by the paper trader.
+pub struct Engine {
+    pub spec: EngineSpec,
+    pub(crate) ctx: Context,
+    pub report: Report,
+}
+
+impl Engine {
+    pub fn new(spec: &EngineSpec) -> Self {
+        Self {
+            spec: spec.clone(),
+            ctx: Context::new(spec.capital),
+            report: Report { fills: Vec::new(), ledger: Vec::new(), liquidations: Vec::new(), positions: Vec::new(), equity: Vec::new() },
+        }
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    // one closed bar with the funding rates settled since the previous one
+    pub fn step<S: Strategy>(&mut self, bar: &Bar, fundings: &[(i64, f64)], strategy: &mut S) {
+        let (spec, ctx, report) = (&self.spec, &mut self.ctx, &mut self.report);
+        let i = bar.index;
+
+        // funding is settled at the open on the position held into the bar
+        let mut ledger = Vec::new();
+        if let Some(model) = &spec.costs {
+            for &(timestamp, rate) in fundings {
+                ledger.push(model.funding(timestamp, rate, ctx.account.position, bar.open));
+            }
+        }
+
+        let mut working = std::mem::take(&mut ctx.open_orders);
+        let mut fills = Vec::new();
+        working.retain_mut(|order| {
+            let outcome = match_order(order, bar);
+            let (price, liquidity) = match outcome {
+                Outcome::Rest => return true,
+                Outcome::Cancel => return false,
+                Outcome::Fill(price, liquidity) => (price, liquidity),
+            };
+            let mut quantity = order.quantity;
+            if order.reduce_only {
+                let position = ctx.account.position;
+                if position == 0f64 || position.signum() == order.side.sign() {
+                    return false;
+                }
+                quantity = quantity.min(position.abs());
+            }
+            if let Some(margin) = &spec.margin {
+                if !margin.allows(&ctx.account, order.side, quantity, price) {
+                    return false;
+                }
+            }
+            ctx.account.apply(order.side, quantity, price);
+            if let Some(model) = &spec.costs {
+                let spread = model.slippage.column().and_then(|column| bar.feature(column));
+                ledger.extend(model.fill(bar.timestamp, liquidity, quantity, price, bar.volume, spread));
+            }
+            fills.push(Fill { order: order.id, bar: i, timestamp: bar.timestamp, side: order.side, quantity, price, liquidity });
+            false
+        });
+        ctx.open_orders = working;
+        ledger.iter().for_each(|entry| ctx.account.book(entry));
+
+        // forced liquidation at the liquidation price once the mark price reached it, what is left of the
+        // maintenance margin goes to the clearance fee and every working order is cancelled
+        let liquidation = spec.margin.as_ref().and_then(|m| Some((m, m.liquidation_price(&ctx.account)?)));
+        if let Some((margin, price)) = liquidation {
+            let mark_low = bar.feature("mark low").unwrap_or(bar.low);
+            let mark_high = bar.feature("mark high").unwrap_or(bar.high);
+            let position = ctx.account.position;
+            if liquidated(position, price, mark_low, mark_high) {
+                let side = if position > 0f64 { Side::Sell } else { Side::Buy };
+                ctx.account.apply(side, position.abs(), price);
+                let entry = Entry::new(bar.timestamp, Cost::Liquidation, -margin.maintenance(position * price));
+                ctx.account.book(&entry);
+                ledger.push(entry);
+                ctx.open_orders.clear();
+                report.liquidations.push(Liquidation { bar: i, timestamp: bar.timestamp, position, price });
+                fills.push(Fill { order: 0, bar: i, timestamp: bar.timestamp, side, quantity: position.abs(), price, liquidity: Liquidity::Taker });
+            }
+        }
+        report.ledger.extend(ledger);
+
+        ctx.mark = bar.close;
+        for fill in &fills {
+            strategy.on_fill(fill, ctx);
+        }
+        report.fills.extend(fills);
+
+        strategy.on_bar(bar, ctx);
+        ctx.settle_orders();
+        report.positions.push(ctx.account.position);
+        report.equity.push(ctx.equity());
+    }
+}
+
+pub fn run_candles<S: Strategy>(candles: &Candles, features: &Features, strategy: &mut S, spec: &EngineSpec) -> Report {
+    let mut engine = Engine::new(spec);
+    engine.report.positions.reserve(candles.len());
+    engine.report.equity.reserve(candles.len());
+    let fundings = spec.costs.as_ref().and_then(|c| c.funding.as_ref()).map(|f| f.per_bar(&candles.timestamp));
+
+    for i in 0..candles.len() {
+        let bar = Bar {
+            index: i,
+            timestamp: candles.timestamp[i],
+            open: candles.open[i],
+            high: candles.high[i],
+            low: candles.low[i],
+            close: candles.close[i],
+            volume: candles.volume[i],
+            features,
+            row: i,
+        };
+        engine.step(&bar, fundings.as_ref().map(|f| f[i].as_slice()).unwrap_or(&[]), strategy);
+    }
+    engine.report
+}
+
+// Replays the output of `aggregate` (or any candle frame) through the strategy.
+pub fn run<S: Strategy>(df: &DataFrame, strategy: &mut S, spec: &EngineSpec) -> Result<Report> {
+    let candles = Candles::from_frame(df)?;
+    let features = Features::from_frame(df)?;
+    Ok(run_candles(&candles, &features, strategy, spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::costs::{Fees, FundingRates, Slippage};
+    use crate::margin::MarginMode;
+
+    fn frame() -> DataFrame {
+        df![
+            "timestamp" => (0..5i64).collect::<Vec<_>>(),
+            "open" => [100.0, 101.0, 103.0, 99.0, 98.0],
+            "high" => [101.0, 104.0, 103.5, 100.0, 99.5],
+            "low" => [99.0, 100.5, 98.5, 97.0, 97.5],
+            "close" => [101.0, 103.0, 99.0, 98.0, 99.0],
+            "volume" => [1.0; 5],
+            "abnormal volume" => [true, false, false, false, false],
+            "trend from base" => ["Bull"; 5],
+            "rising float" => [0.1; 5],
+        ].unwrap()
+    }
+
+    // submits the orders of a bar index once, and records what it saw
+    struct Scripted {
+        orders: Vec<(usize, Order)>,
+        ids: Vec<u64>,
+        seen: Vec<Option<f64>>,
+    }
+
+    impl Strategy for Scripted {
+        fn on_bar(&mut self, bar: &Bar, ctx: &mut Context) {
+            self.seen.push(bar.feature("abnormal volume"));
+            assert_eq!(bar.feature("rising float"), None);
+            for (_, order) in self.orders.iter().filter(|(at, _)| *at == bar.index) {
+                self.ids.push(ctx.submit(order.clone()));
+            }
+        }
+    }
+
+    fn run_script(orders: Vec<(usize, Order)>) -> Report {
+        let mut strategy = Scripted { orders, ids: Vec::new(), seen: Vec::new() };
+        let report = run(&frame(), &mut strategy, &EngineSpec::default()).unwrap();
+        assert_eq!(strategy.seen[0], Some(1f64));
+        report
+    }
+
+    #[test]
+    fn test_market_and_stop() {
+        let report = run_script(vec![
+            (0, Order::market(Side::Buy, 1.0)),
+            // protective stop under the market, triggered within the third bar
+            (1, Order::stop(Side::Sell, 1.0, 99.5).reduce_only()),
+        ]);
+        assert_eq!(report.fills.len(), 2);
+        assert_eq!((report.fills[0].bar, report.fills[0].price), (1, 101.0));
+        // the stop of 99.5 triggered within the third bar, at 99.5 since it did not gap
+        assert_eq!((report.fills[1].bar, report.fills[1].price), (2, 99.5));
+        assert_eq!(report.positions, vec![0.0, 1.0, 0.0, 0.0, 0.0]);
+        assert!((report.equity[4] - (10_000.0 - 1.5)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_limits() {
+        let report = run_script(vec![
+            // resting bid at 98.5 is only touched by the low of the third bar, and traded through on the fourth
+            (1, Order::limit(Side::Buy, 1.0, 98.5)),
+            // marketable at the open of the second bar
+            (0, Order::limit(Side::Sell, 2.0, 100.0)),
+            // post only would take liquidity at the open, so it is rejected
+            (0, Order::limit(Side::Sell, 1.0, 100.0).post_only()),
+        ]);
+        let fills = report.fills.iter().map(|f| (f.bar, f.side, f.quantity, f.price, f.liquidity)).collect::<Vec<_>>();
+        assert_eq!(
+            fills,
+            vec![(1, Side::Sell, 2.0, 101.0, Liquidity::Taker), (3, Side::Buy, 1.0, 98.5, Liquidity::Maker)]
+        );
+        assert_eq!(*report.positions.last().unwrap(), -1.0);
+    }
+
+    #[test]
+    fn test_costs() {
+        let model = CostModel::new(Fees::vip(0), Slippage::Fixed(1.0))
+            .with_funding(FundingRates { timestamp: vec![2], rate: vec![0.001] });
+        let spec = EngineSpec { costs: Some(model), ..Default::default() };
+        let mut strategy = Scripted { orders: vec![(0, Order::market(Side::Buy, 1.0))], ids: Vec::new(), seen: Vec::new() };
+        let report = run(&frame(), &mut strategy, &spec).unwrap();
+
+        // fee and slippage of the buy at 101, then the long pays the funding at the open of 103
+        let expected = [(1, Cost::Fee, -101.0 * 0.0004), (1, Cost::Slippage, -101.0 * 0.0001), (2, Cost::Funding, -103.0 * 0.001)];
+        assert_eq!(report.ledger.len(), 3);
+        for (entry, (timestamp, cost, amount)) in report.ledger.iter().zip(expected) {
+            assert_eq!((entry.timestamp, entry.cost), (timestamp, cost));
+            assert!((entry.amount - amount).abs() < 1e-12);
+        }
+        let paid = expected.iter().map(|e| e.2).sum::<f64>();
+        assert!((report.equity[4] - (10_000.0 + 99.0 - 101.0 + paid)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_liquidation() {
+        // 100 at 101 with 50x isolated is liquidated as the low of 98.5 passes 101 * (1 - 1 / 50) / 0.996
+        let spec = EngineSpec { margin: Some(MarginSpec::new(MarginMode::Isolated, 50.0)), ..Default::default() };
+        let orders = vec![(0, Order::market(Side::Buy, 100.0)), (1, Order::limit(Side::Sell, 100.0, 110.0))];
+        let mut strategy = Scripted { orders, ids: Vec::new(), seen: Vec::new() };
+        let report = run(&frame(), &mut strategy, &spec).unwrap();
+
+        assert_eq!(report.liquidations.len(), 1);
+        let liquidation = &report.liquidations[0];
+        assert_eq!((liquidation.bar, liquidation.position), (2, 100.0));
+        assert!((liquidation.price - 101.0 * 0.98 / 0.996).abs() < 1e-9);
+        // the take profit was cancelled and the isolated margin is lost, not more
+        assert_eq!(report.fills.iter().map(|f| f.order).collect::<Vec<_>>(), vec![1, 0]);
+        assert!((report.equity[4] - (10_000.0 - 100.0 * 101.0 / 50.0)).abs() < 1e-6);
+
+        // 200x is over the max leverage of the first bracket, the order is rejected
+        let spec = EngineSpec { margin: Some(MarginSpec::new(MarginMode::Cross, 200.0)), ..Default::default() };
+        let mut strategy = Scripted { orders: vec![(0, Order::market(Side::Buy, 1.0))], ids: Vec::new(), seen: Vec::new() };
+        assert!(run(&frame(), &mut strategy, &spec).unwrap().fills.is_empty());
+    }
+
+    #[test]
+    fn test_stop_limit() {
+        let report = run_script(vec![
+            // buy stop at 103.8 triggers within the second bar and is filled at the stop under its limit
+            (0, Order::stop_limit(Side::Buy, 1.0, 103.8, 103.9)),
+            // sell stop at 98 triggers on the fourth bar after the gap down to an open of 99
+            (0, Order::stop_limit(Side::Sell, 1.0, 98.0, 97.0)),
+        ]);
+        let fills = report.fills.iter().map(|f| (f.bar, f.side, f.price)).collect::<Vec<_>>();
+        assert_eq!(fills, vec![(1, Side::Buy, 103.8), (3, Side::Sell, 98.0)]);
+    }
+}
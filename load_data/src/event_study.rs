@@ -0,0 +1,189 @@
+use polars::prelude::*;
+
+use crate::data::{bool_column, f64_column, timestamp_series, Candles};
+use crate::stats;
+
+// Event study of close to close returns around the events of a mask column,
+// e.g. `abnormal volume` of `aggregate`, from `before` bars ahead to `after` bars past each event.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    Zero,                           // abnormal returns are raw returns
+    ConstantMean { window: usize }, // mean return of the `window` bars ahead of the event window
+    Benchmark(String),              // a column of benchmark returns
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventStudySpec {
+    pub before: usize,
+    pub after: usize,
+    pub expected: Expected,
+    pub confidence: f64,
+}
+
+impl Default for EventStudySpec {
+    fn default() -> Self {
+        Self { before: 10, after: 20, expected: Expected::ConstantMean { window: 100 }, confidence: 0.95 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventStudy {
+    pub summary: DataFrame, // one row per offset from the event
+    pub detail: DataFrame,  // one row per event and offset
+}
+
+fn returns(candles: &Candles) -> Vec<f64> {
+    (0..candles.len())
+        .map(|i| if i == 0 { f64::NAN } else { candles.close[i] / candles.close[i - 1] - 1f64 })
+        .collect()
+}
+
+// abnormal returns of an event from `before` bars ahead, None when the window is not covered
+fn abnormal_returns(r: &[f64], benchmark: Option<&[f64]>, spec: &EventStudySpec, event: usize) -> Option<Vec<f64>> {
+    let start = event.checked_sub(spec.before)?;
+    let end = event + spec.after;
+    if end >= r.len() {
+        return None;
+    }
+    let expected = match &spec.expected {
+        Expected::ConstantMean { window } => stats::mean(&r[start.checked_sub(*window)?..start]),
+        _ => 0f64,
+    };
+    let ar = (start..=end)
+        .map(|i| r[i] - expected - benchmark.map(|b| b[i]).unwrap_or(0f64))
+        .collect::<Vec<_>>();
+    if ar.iter().all(|x| x.is_finite()) && expected.is_finite() {
+        Some(ar)
+    } else {
+        None
+    }
+}
+
+pub fn event_study(df: &DataFrame, events: &str, spec: &EventStudySpec) -> Result<EventStudy> {
+    let candles = Candles::from_frame(df)?;
+    let r = returns(&candles);
+    let benchmark = match &spec.expected {
+        Expected::Benchmark(column) => Some(f64_column(df, column)?),
+        _ => None,
+    };
+
+    let (mut stamps, mut ars) = (Vec::new(), Vec::new());
+    for (event, _) in bool_column(df, events)?.iter().enumerate().filter(|(_, e)| **e) {
+        if let Some(ar) = abnormal_returns(&r, benchmark.as_deref(), spec, event) {
+            stamps.push(candles.timestamp[event]);
+            ars.push(ar);
+        }
+    }
+    let cars = ars
+        .iter()
+        .map(|ar| ar.iter().scan(0f64, |car, x| { *car += x; Some(*car) }).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    Ok(EventStudy { summary: summary(spec, &ars, &cars)?, detail: detail(spec, &stamps, &ars, &cars)? })
+}
+
+fn offsets(spec: &EventStudySpec) -> Vec<i32> {
+    (-(spec.before as i32)..=spec.after as i32).collect()
+}
+
+// car up to the bar ahead of the event
+fn ahead_of_event(spec: &EventStudySpec, car: &[f64]) -> f64 {
+    if spec.before > 0 {
+        car[spec.before - 1]
+    } else {
+        0f64
+    }
+}
+
+fn summary(spec: &EventStudySpec, ars: &[Vec<f64>], cars: &[Vec<f64>]) -> Result<DataFrame> {
+    let z = stats::normal_quantile(0.5 + spec.confidence / 2f64);
+    let column = |rows: &[Vec<f64>], i: usize| rows.iter().map(|row| row[i]).collect::<Vec<_>>();
+    let offsets = offsets(spec);
+
+    let (mut mean_ar, mut mean_car, mut median_car, mut lower, mut upper, mut hit_rate) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (i, offset) in offsets.iter().enumerate() {
+        let car = column(cars, i);
+        let m = stats::mean(&car);
+        let half = z * stats::std(&car) / (car.len() as f64).sqrt();
+        mean_ar.push(stats::mean(&column(ars, i)));
+        mean_car.push(m);
+        median_car.push(stats::median(&car));
+        lower.push(m - half);
+        upper.push(m + half);
+        // how often the return cumulated since the event bar is positive
+        hit_rate.push(if *offset < 0 {
+            None
+        } else {
+            let hits = cars.iter().filter(|car| car[i] - ahead_of_event(spec, car) > 0f64).count();
+            Some(hits as f64 / cars.len() as f64)
+        });
+    }
+
+    DataFrame::new(vec![
+        Series::new("offset", offsets),
+        Series::new("events", vec![cars.len() as u32; spec.before + spec.after + 1]),
+        Series::new("mean abnormal return", mean_ar),
+        Series::new("mean car", mean_car),
+        Series::new("median car", median_car),
+        Series::new("car lower", lower),
+        Series::new("car upper", upper),
+        Series::new("hit rate", hit_rate),
+    ])
+}
+
+fn detail(spec: &EventStudySpec, stamps: &[i64], ars: &[Vec<f64>], cars: &[Vec<f64>]) -> Result<DataFrame> {
+    let offsets = offsets(spec);
+    let width = offsets.len();
+    DataFrame::new(vec![
+        timestamp_series("timestamp", stamps.iter().flat_map(|t| std::iter::repeat_n(*t, width)).collect())?,
+        Series::new("offset", stamps.iter().flat_map(|_| offsets.iter().copied()).collect::<Vec<_>>()),
+        Series::new("abnormal return", ars.concat()),
+        Series::new("car", cars.concat()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_study() {
+        // flat at 1% a bar except the bars right after each event
+        let mut close = vec![100f64];
+        let events = [10usize, 20, 30];
+        for i in 1..40 {
+            let r = match events.iter().find(|e| i == **e + 1) {
+                Some(e) if *e == 30 => -0.01,
+                Some(_) => 0.05,
+                None => 0.01,
+            };
+            close.push(close[i - 1] * (1f64 + r));
+        }
+        let df = df![
+            "timestamp" => (0..40i64).collect::<Vec<_>>(),
+            "open" => close.clone(),
+            "high" => close.clone(),
+            "low" => close.clone(),
+            "close" => close,
+            "volume" => vec![1f64; 40],
+            "abnormal volume" => (0..40).map(|i| events.contains(&i)).collect::<Vec<_>>(),
+        ].unwrap();
+        let spec = EventStudySpec { before: 2, after: 3, expected: Expected::ConstantMean { window: 5 }, confidence: 0.95 };
+        let study = event_study(&df, "abnormal volume", &spec).unwrap();
+
+        assert_eq!(study.summary.height(), 6);
+        assert_eq!(study.detail.height(), 3 * 6);
+        let summary = |name: &str| study.summary.column(name).unwrap().f64().unwrap().into_iter().collect::<Vec<_>>();
+        // abnormal returns are only seen on the bar after the event
+        let ar = summary("mean abnormal return");
+        assert!(ar[0].unwrap().abs() < 1e-9 && ar[2].unwrap().abs() < 1e-9);
+        assert!((ar[3].unwrap() - (0.04 + 0.04 - 0.02) / 3f64).abs() < 1e-9);
+        // two of three events went up
+        assert_eq!(summary("hit rate")[1], None);
+        assert!((summary("hit rate")[3].unwrap() - 2f64 / 3f64).abs() < 1e-12);
+        let (lower, upper) = (summary("car lower")[3].unwrap(), summary("car upper")[3].unwrap());
+        assert!(lower < summary("mean car")[3].unwrap() && summary("mean car")[3].unwrap() < upper);
+    }
+}
@@ -0,0 +1,213 @@
+use polars::prelude::*;
+
+use crate::data::Trend;
+use crate::neighbors::Metric;
+use crate::pattern::{forward_return_name, PatternSpec};
+
+// How the similarity of a match turns into its weight in the forecast, by the matcher that found it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weighting {
+    Similarity,      // cosine similarities, negative ones are anti-patterns and weigh nothing
+    InverseDistance, // 1 / (1 + d) of the negated distances of `Dtw` and `Metric::L1`
+}
+
+impl Weighting {
+    pub fn of(metric: Metric) -> Self {
+        match metric {
+            Metric::Cosine => Weighting::Similarity,
+            Metric::L1 => Weighting::InverseDistance,
+        }
+    }
+
+    // between 0 and 1 for both, 1 for an identical window
+    fn weight(&self) -> Expr {
+        match self {
+            Weighting::Similarity => when(col("similarity").gt(lit(0f64)))
+                .then(col("similarity"))
+                .otherwise(lit(0f64)),
+            Weighting::InverseDistance => lit(1f64) / (lit(1f64) - col("similarity")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ForecastSpec {
+    pub horizons: Vec<usize>,
+    pub quantiles: Vec<f64>,
+    pub weighting: Weighting,
+}
+
+impl ForecastSpec {
+    pub fn new(spec: &PatternSpec) -> Self {
+        Self { horizons: spec.horizons.clone(), quantiles: vec![0.1, 0.5, 0.9], weighting: Weighting::Similarity }
+    }
+}
+
+fn weighted_mean(value: Expr, weighting: Weighting) -> Expr {
+    weighted_mean_where(value.clone(), value, weighting)
+}
+
+// weighted mean of `value` over the matches where `known` is not null
+fn weighted_mean_where(value: Expr, known: Expr, weighting: Weighting) -> Expr {
+    let w = when(known.is_not_null()).then(weighting.weight()).otherwise(lit(0f64));
+    (w.clone() * value).sum() / w.sum()
+}
+
+fn direction(value: Expr) -> Expr {
+    when(value.clone().gt(lit(0f64)))
+        .then(lit(1f64))
+        .otherwise(when(value.lt(lit(0f64))).then(lit(-1f64)).otherwise(lit(0f64)))
+}
+
+fn distribution(horizon: usize, spec: &ForecastSpec) -> Vec<Expr> {
+    let r = forward_return_name(horizon);
+    let mut exprs = vec![
+        col(&r).mean().alias(&format!("mean {}", r)),
+        col(&r).std().alias(&format!("std {}", r)),
+        weighted_mean(col(&r), spec.weighting).alias(&format!("weighted mean {}", r)),
+        // weighted agreement on the direction times how similar the matches are
+        (weighted_mean_where(direction(col(&r)), col(&r), spec.weighting).abs()
+            * weighted_mean(spec.weighting.weight(), spec.weighting))
+            .alias(&format!("confidence {}", r)),
+    ];
+    exprs.extend(spec.quantiles.iter().map(|q| {
+        col(&r)
+            .quantile(*q, QuantileInterpolOptions::Linear)
+            .alias(&format!("q{} {}", (q * 100f64).round(), r))
+    }));
+    exprs
+}
+
+pub fn probability_name(trend: Trend) -> String {
+    format!("p {}", trend.name().to_lowercase())
+}
+
+fn trend_probabilities(weighting: Weighting) -> Vec<Expr> {
+    Trend::ALL
+        .iter()
+        .map(|trend| {
+            weighted_mean(col("trend from base").eq(trend.lit()).cast(DataType::Float64), weighting)
+                .alias(&probability_name(*trend))
+        })
+        .collect()
+}
+
+// Outcome distribution of the matches of each timestamp, one row per timestamp.
+pub fn analog_forecast(matches: &DataFrame, spec: &ForecastSpec) -> Result<DataFrame> {
+    let mut aggs = vec![
+        count().alias("matches"),
+        col("similarity").mean().alias("mean similarity"),
+    ];
+    for &horizon in &spec.horizons {
+        aggs.extend(distribution(horizon, spec));
+    }
+    if matches.column("trend from base").is_ok() {
+        aggs.extend(trend_probabilities(spec.weighting));
+    }
+
+    matches
+        .clone()
+        .lazy()
+        .groupby([col("timestamp")])
+        .agg(aggs)
+        .sort("timestamp", SortOptions::default())
+        .collect()
+}
+
+// The forecast as signal columns of `df`, null where a timestamp had no matches.
+pub fn with_analog_forecast(df: &DataFrame, forecast: &DataFrame) -> Result<DataFrame> {
+    df.join(forecast, ["timestamp"], ["timestamp"], JoinType::Left, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtw::Dtw;
+    use crate::neighbors::similar_patterns_indexed;
+    use crate::pattern::{mean_scaling, similar_patterns_with};
+
+    // two waves, labelled with the direction of the next close
+    fn waves(spec: &PatternSpec) -> DataFrame {
+        let close = (0..240).map(|i| 100f64 + (i as f64 / 6f64).sin() + (i as f64 / 17f64).cos()).collect::<Vec<_>>();
+        let trend = (0..240)
+            .map(|i| match close.get(i + 1) {
+                Some(next) if *next > close[i] => "Bull",
+                Some(_) => "Bear",
+                None => "Unknown",
+            })
+            .collect::<Vec<_>>();
+        let mut df = df![
+            "timestamp" => (0..240i64).collect::<Vec<_>>(),
+            "close" => close,
+            "trend from base" => trend,
+        ].unwrap();
+        df.apply("trend from base", |s| s.cast(&Trend::dtype()).unwrap()).unwrap();
+        mean_scaling(df.lazy(), spec).collect().unwrap()
+    }
+
+    // every forecast is a proper weighted one although all the similarities are negated distances
+    fn assert_weighted(matches: &DataFrame, weighting: Weighting) {
+        assert!(matches.column("similarity").unwrap().f64().unwrap().into_no_null_iter().all(|s| s <= 0f64));
+        let spec = ForecastSpec { horizons: vec![3], quantiles: vec![0.5], weighting };
+        let forecast = analog_forecast(matches, &spec).unwrap();
+        assert!(forecast.height() > 0);
+        let column = |name: &str| forecast.column(name).unwrap().f64().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert!(column("weighted mean forward return 3").iter().all(|m| m.is_finite()));
+        assert!(column("confidence forward return 3").iter().all(|c| (0f64..=1f64).contains(c)));
+        let total = Trend::ALL.iter().map(|trend| column(&probability_name(*trend))).fold(vec![0f64; forecast.height()], |total, p| {
+            total.iter().zip(p).map(|(t, p)| t + p).collect()
+        });
+        assert!(total.iter().all(|t| (t - 1f64).abs() < 1e-9));
+    }
+
+    fn spec() -> PatternSpec {
+        PatternSpec { window: 10, top_k: 4, features: vec!["close".into()], horizons: vec![3], ..Default::default() }
+    }
+
+    #[test]
+    fn test_analog_forecast() {
+        let mut matches = df![
+            "timestamp" => [10i64, 10, 10, 20, 20],
+            "rank" => [1u32, 2, 3, 1, 2],
+            "similarity" => [0.9f64, 0.6, -0.2, 0.8, 0.8],
+            "forward return 4" => [Some(0.02f64), Some(0.01), Some(-0.05), Some(-0.01), None],
+            "trend from base" => ["Bull", "Bull", "Bear", "Bear", "Unknown"],
+        ].unwrap();
+        matches.apply("trend from base", |s| s.cast(&Trend::dtype()).unwrap()).unwrap();
+        let spec = ForecastSpec { horizons: vec![4], quantiles: vec![0.5], weighting: Weighting::Similarity };
+        let forecast = analog_forecast(&matches, &spec).unwrap();
+
+        assert_eq!(forecast.height(), 2);
+        let get = |name: &str, row: usize| forecast.column(name).unwrap().f64().unwrap().get(row).unwrap();
+
+        // the anti-pattern at -0.2 similarity is counted in the distribution but carries no weight
+        assert!((get("weighted mean forward return 4", 0) - (0.9 * 0.02 + 0.6 * 0.01) / 1.5).abs() < 1e-12);
+        assert!((get("q50 forward return 4", 0) - 0.01).abs() < 1e-12);
+        assert!((get("p bull", 0) - 1f64).abs() < 1e-12);
+        assert!((get("p bull", 1) - 0f64).abs() < 1e-12);
+        assert!((get("p bear", 1) - 0.5).abs() < 1e-12);
+        assert!((get("confidence forward return 4", 0) - 1f64 * (0.9 * 0.9 + 0.6 * 0.6) / 1.5).abs() < 1e-12);
+
+        let source = df!["timestamp" => [0i64, 10, 20, 30]].unwrap();
+        let signal = with_analog_forecast(&source, &forecast).unwrap();
+        assert_eq!(signal.height(), 4);
+        assert_eq!(signal.column("matches").unwrap().null_count(), 2);
+    }
+
+    #[test]
+    fn test_dtw_forecast() {
+        let spec = spec();
+        let df = waves(&spec);
+        let queries = (150..230).collect::<Vec<_>>();
+        let matches = similar_patterns_with(&Dtw::new(&spec, 2), &df, &spec, Some(&queries)).unwrap();
+        assert_weighted(&matches, Weighting::InverseDistance);
+    }
+
+    #[test]
+    fn test_l1_forecast() {
+        let spec = spec();
+        let df = waves(&spec);
+        let matches = similar_patterns_indexed(&df, &spec, Metric::L1).unwrap();
+        assert_weighted(&matches, Weighting::of(Metric::L1));
+    }
+}
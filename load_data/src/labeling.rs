@@ -0,0 +1,473 @@
+use polars::prelude::*;
+
+use crate::data::{bool_column, timestamp_series, Candles, Trend};
+
+// Triple-barrier labeling: an event entered at the open of its bar is labeled by whichever of
+// the upper, lower or vertical (time limit) barrier it touches first.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Width {
+    Fixed(f64),      // a return, e.g. 0.01 for 1%
+    Volatility(f64), // a multiple of the rolling std of close to close returns
+}
+
+// What to assume when both barriers fall inside one bar and finer data can't tell the order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TieBreak {
+    Pessimistic,  // the lower barrier first, the worst case of a long entry
+    Optimistic,   // the upper barrier first
+    OpenDistance, // the barrier nearer to the open of the bar first
+}
+
+impl TieBreak {
+    // the same assumption from the point of view of a short entry
+    pub fn for_short(&self) -> Self {
+        match self {
+            TieBreak::Pessimistic => TieBreak::Optimistic,
+            TieBreak::Optimistic => TieBreak::Pessimistic,
+            TieBreak::OpenDistance => TieBreak::OpenDistance,
+        }
+    }
+
+    pub fn assume(&self, open: f64, upper: f64, lower: f64) -> Barrier {
+        match self {
+            TieBreak::Pessimistic => Barrier::Lower,
+            TieBreak::Optimistic => Barrier::Upper,
+            TieBreak::OpenDistance if upper - open < open - lower => Barrier::Upper,
+            TieBreak::OpenDistance => Barrier::Lower,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarrierSpec {
+    pub upper: Width,
+    pub lower: Width,
+    pub horizon: usize,    // bars until the vertical barrier, the event bar included
+    pub vol_window: usize, // bars of returns for `Width::Volatility`
+    pub tie_break: TieBreak,
+}
+
+impl BarrierSpec {
+    pub fn fixed(upper: f64, lower: f64, horizon: usize) -> Self {
+        Self {
+            upper: Width::Fixed(upper),
+            lower: Width::Fixed(lower),
+            horizon,
+            vol_window: 20,
+            tie_break: TieBreak::Pessimistic,
+        }
+    }
+
+    pub fn volatility(upper: f64, lower: f64, horizon: usize, vol_window: usize) -> Self {
+        Self {
+            upper: Width::Volatility(upper),
+            lower: Width::Volatility(lower),
+            horizon,
+            vol_window,
+            tie_break: TieBreak::Pessimistic,
+        }
+    }
+
+    pub fn with_tie_break(self, tie_break: TieBreak) -> Self {
+        Self { tie_break, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Barrier {
+    Upper,
+    Lower,
+    Vertical,
+}
+
+impl Barrier {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Barrier::Upper => "Upper",
+            Barrier::Lower => "Lower",
+            Barrier::Vertical => "Vertical",
+        }
+    }
+
+    // the ordered counterpart of `trend from base`, which has no notion of which came first
+    pub fn trend(&self) -> Trend {
+        match self {
+            Barrier::Upper => Trend::Bull,
+            Barrier::Lower => Trend::Bear,
+            Barrier::Vertical => Trend::Unknown,
+        }
+    }
+}
+
+// How the first touch of a label was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    Bar,      // a single barrier was touched within the bar
+    Intrabar, // both were, and the lower timeframe told which came first
+    Assumed,  // both were, and the tie break decided
+}
+
+impl Resolution {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Resolution::Bar => "Bar",
+            Resolution::Intrabar => "Intrabar",
+            Resolution::Assumed => "Assumed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub event: usize,
+    pub entry: f64,
+    pub upper: f64, // barrier prices
+    pub lower: f64,
+    pub barrier: Barrier,
+    pub touch: usize, // bar of the touch, the last bar for the vertical barrier
+    pub realized: f64,
+    pub resolution: Resolution,
+}
+
+enum Touch {
+    Neither,
+    One(Barrier),
+    Both,
+}
+
+fn touched(open: f64, high: f64, low: f64, upper: f64, lower: f64) -> Touch {
+    // a bar opening beyond a barrier touched it first
+    if open >= upper {
+        return Touch::One(Barrier::Upper);
+    }
+    if open <= lower {
+        return Touch::One(Barrier::Lower);
+    }
+    match (high >= upper, low <= lower) {
+        (true, true) => Touch::Both,
+        (true, false) => Touch::One(Barrier::Upper),
+        (false, true) => Touch::One(Barrier::Lower),
+        (false, false) => Touch::Neither,
+    }
+}
+
+fn bar_end(candles: &Candles, bar: usize) -> i64 {
+    let t = candles.timestamp[bar];
+    match (candles.timestamp.get(bar + 1), bar.checked_sub(1)) {
+        (Some(next), _) => *next,
+        (None, Some(previous)) => t + (t - candles.timestamp[previous]),
+        (None, None) => i64::MAX,
+    }
+}
+
+// The order of a tie from the finer candles (or trades) within the bar, if they cover it.
+fn intrabar(fine: &Candles, start: i64, end: i64, upper: f64, lower: f64) -> Option<Barrier> {
+    let from = fine.timestamp.partition_point(|t| *t < start);
+    for i in from..fine.len() {
+        if fine.timestamp[i] >= end {
+            break;
+        }
+        match touched(fine.open[i], fine.high[i], fine.low[i], upper, lower) {
+            Touch::One(barrier) => return Some(barrier),
+            Touch::Both => return None,
+            Touch::Neither => continue,
+        }
+    }
+    None
+}
+
+// Rolling std of close to close returns known at the open of each bar.
+pub fn volatility(candles: &Candles, window: usize) -> Vec<f64> {
+    let returns = (0..candles.len())
+        .map(|i| if i == 0 { f64::NAN } else { candles.close[i] / candles.close[i - 1] - 1f64 })
+        .collect::<Vec<_>>();
+    (0..candles.len())
+        .map(|i| {
+            // returns up to the close of the previous bar
+            if window < 2 || i < window + 1 {
+                return f64::NAN;
+            }
+            let sample = &returns[i - window..i];
+            let mean = sample.iter().sum::<f64>() / window as f64;
+            (sample.iter().map(|r| (r - mean) * (r - mean)).sum::<f64>() / (window - 1) as f64).sqrt()
+        })
+        .collect()
+}
+
+fn width(width: Width, vol: f64) -> f64 {
+    match width {
+        Width::Fixed(w) => w,
+        Width::Volatility(k) => k * vol,
+    }
+}
+
+// Barriers of an event, None when the volatility is not known yet.
+pub fn barriers(candles: &Candles, spec: &BarrierSpec, vol: &[f64], event: usize) -> Option<(f64, f64)> {
+    let entry = candles.open[event];
+    let upper = width(spec.upper, vol[event]);
+    let lower = width(spec.lower, vol[event]);
+    if !upper.is_finite() || !lower.is_finite() {
+        return None;
+    }
+    Some((entry * (1f64 + upper), entry * (1f64 - lower)))
+}
+
+pub fn label_event(candles: &Candles, spec: &BarrierSpec, vol: &[f64], event: usize) -> Option<Label> {
+    label_event_with(candles, None, spec, vol, event)
+}
+
+// Like `label_event`, drilling into `fine` (1m candles or trades) for bars touching both barriers.
+pub fn label_event_with(
+    candles: &Candles,
+    fine: Option<&Candles>,
+    spec: &BarrierSpec,
+    vol: &[f64],
+    event: usize,
+) -> Option<Label> {
+    let (upper, lower) = barriers(candles, spec, vol, event)?;
+    let entry = candles.open[event];
+    let last = (event + spec.horizon.max(1) - 1).min(candles.len() - 1);
+
+    for bar in event..=last {
+        let open = candles.open[bar];
+        let (barrier, resolution) = match touched(open, candles.high[bar], candles.low[bar], upper, lower) {
+            Touch::Neither => continue,
+            Touch::One(barrier) => (barrier, Resolution::Bar),
+            Touch::Both => match fine.and_then(|fine| intrabar(fine, candles.timestamp[bar], bar_end(candles, bar), upper, lower)) {
+                Some(barrier) => (barrier, Resolution::Intrabar),
+                None => (spec.tie_break.assume(open, upper, lower), Resolution::Assumed),
+            },
+        };
+        // a gap through the barrier fills at the open
+        let price = match barrier {
+            Barrier::Upper => upper.max(open),
+            _ => lower.min(open),
+        };
+        return Some(Label {
+            event,
+            entry,
+            upper,
+            lower,
+            barrier,
+            touch: bar,
+            realized: price / entry - 1f64,
+            resolution,
+        });
+    }
+
+    // the vertical barrier only stands when the whole horizon is known
+    if last < event + spec.horizon.max(1) - 1 {
+        return None;
+    }
+    Some(Label {
+        event,
+        entry,
+        upper,
+        lower,
+        barrier: Barrier::Vertical,
+        touch: last,
+        realized: candles.close[last] / entry - 1f64,
+        resolution: Resolution::Bar,
+    })
+}
+
+pub fn label_events(candles: &Candles, spec: &BarrierSpec, events: &[bool]) -> Vec<Label> {
+    label_events_with(candles, None, spec, events)
+}
+
+pub fn label_events_with(candles: &Candles, fine: Option<&Candles>, spec: &BarrierSpec, events: &[bool]) -> Vec<Label> {
+    let vol = volatility(candles, spec.vol_window);
+    events
+        .iter()
+        .enumerate()
+        .filter(|(_, event)| **event)
+        .filter_map(|(event, _)| label_event_with(candles, fine, spec, &vol, event))
+        .collect()
+}
+
+// Labels of the rows of `df` flagged by the boolean column `events`.
+pub fn triple_barrier(df: &DataFrame, spec: &BarrierSpec, events: &str) -> Result<DataFrame> {
+    let candles = Candles::from_frame(df)?;
+    let labels = label_events(&candles, spec, &bool_column(df, events)?);
+    labels_to_frame(&candles, &labels)
+}
+
+// `triple_barrier` resolving ties from a lower timeframe, candles or trades with a `price` column
+// read by `Candles::from_trades`.
+pub fn triple_barrier_intrabar(df: &DataFrame, fine: &DataFrame, spec: &BarrierSpec, events: &str) -> Result<DataFrame> {
+    let candles = Candles::from_frame(df)?;
+    let fine = match fine.column("price") {
+        Ok(_) => Candles::from_trades(fine)?,
+        Err(_) => Candles::from_frame(fine)?,
+    };
+    let labels = label_events_with(&candles, Some(&fine), spec, &bool_column(df, events)?);
+    labels_to_frame(&candles, &labels)
+}
+
+pub fn labels_to_frame(candles: &Candles, labels: &[Label]) -> Result<DataFrame> {
+    let field = |f: fn(&Label) -> f64| labels.iter().map(f).collect::<Vec<_>>();
+    DataFrame::new(vec![
+        timestamp_series("timestamp", labels.iter().map(|l| candles.timestamp[l.event]).collect())?,
+        Series::new("entry price", field(|l| l.entry)),
+        Series::new("upper barrier", field(|l| l.upper)),
+        Series::new("lower barrier", field(|l| l.lower)),
+        Series::new("barrier", labels.iter().map(|l| l.barrier.name()).collect::<Vec<_>>())
+            .cast(&DataType::Categorical(None))?,
+        Series::new("trend from barrier", labels.iter().map(|l| l.barrier.trend().name()).collect::<Vec<_>>())
+            .cast(&Trend::dtype())?,
+        timestamp_series("touch timestamp", labels.iter().map(|l| candles.timestamp[l.touch]).collect())?,
+        Series::new("bars to touch", labels.iter().map(|l| (l.touch - l.event) as u32).collect::<Vec<_>>()),
+        Series::new("realized return", field(|l| l.realized)),
+        Series::new("resolution", labels.iter().map(|l| l.resolution.name()).collect::<Vec<_>>())
+            .cast(&DataType::Categorical(None))?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candles(bars: &[(f64, f64, f64, f64)]) -> Candles {
+        Candles {
+            timestamp: (0..bars.len() as i64).map(|i| i * 900_000).collect(),
+            open: bars.iter().map(|b| b.0).collect(),
+            high: bars.iter().map(|b| b.1).collect(),
+            low: bars.iter().map(|b| b.2).collect(),
+            close: bars.iter().map(|b| b.3).collect(),
+            volume: vec![1f64; bars.len()],
+        }
+    }
+
+    #[test]
+    fn test_first_touch() {
+        let candles = candles(&[
+            (100.0, 100.5, 99.5, 100.0),
+            (100.0, 102.5, 99.8, 102.0), // upper first
+            (102.0, 102.0, 97.0, 97.5),  // then lower
+            (97.5, 98.0, 97.0, 97.5),
+        ]);
+        let spec = BarrierSpec::fixed(0.02, 0.02, 4);
+        let labels = label_events(&candles, &spec, &[true, false, false, false]);
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].barrier, Barrier::Upper);
+        assert_eq!(labels[0].touch, 1);
+        assert!((labels[0].realized - 0.02).abs() < 1e-12);
+
+        // the third bar opens at 102 and falls through 99.96 within the limit of two bars
+        let labels = label_events(&candles, &BarrierSpec::fixed(0.05, 0.02, 2), &[false, false, true, false]);
+        assert_eq!(labels[0].barrier, Barrier::Lower);
+        assert_eq!(labels[0].touch, 2);
+
+        // nothing is touched within the limit
+        let labels = label_events(&candles, &BarrierSpec::fixed(0.05, 0.05, 2), &[true, false, false, false]);
+        assert_eq!(labels[0].barrier, Barrier::Vertical);
+        assert_eq!(labels[0].touch, 1);
+        assert!((labels[0].realized - 0.02).abs() < 1e-12);
+
+        // the limit runs past the data
+        assert!(label_events(&candles, &BarrierSpec::fixed(0.05, 0.05, 3), &[false, false, true, false]).is_empty());
+    }
+
+    #[test]
+    fn test_tie_break() {
+        // both barriers of 99 and 101.2 within the event bar
+        let candles = candles(&[(100.2, 101.5, 98.5, 100.0), (100.0, 100.0, 100.0, 100.0)]);
+        let fine = Candles {
+            timestamp: vec![0, 60_000, 120_000, 900_000],
+            open: vec![100.2, 100.5, 99.5, 100.0],
+            high: vec![100.6, 100.8, 101.5, 100.0],
+            low: vec![100.1, 98.5, 99.4, 100.0],
+            close: vec![100.5, 99.5, 101.0, 100.0],
+            volume: vec![1f64; 4],
+        };
+        let label = |spec: BarrierSpec, fine: Option<&Candles>| {
+            let spec = BarrierSpec { upper: Width::Fixed(1.0 / 100.2), lower: Width::Fixed(1.2 / 100.2), ..spec };
+            label_events_with(&candles, fine, &spec, &[true, false]).remove(0)
+        };
+        let spec = BarrierSpec::fixed(0.0, 0.0, 2);
+
+        let assumed = label(spec, None);
+        assert_eq!((assumed.barrier, assumed.resolution), (Barrier::Lower, Resolution::Assumed));
+        let assumed = label(spec.with_tie_break(TieBreak::Optimistic), None);
+        assert_eq!(assumed.barrier, Barrier::Upper);
+        // the open of 100.2 is nearer to the upper barrier
+        let assumed = label(spec.with_tie_break(TieBreak::OpenDistance), None);
+        assert_eq!(assumed.barrier, Barrier::Upper);
+
+        // the second minute fell through the lower barrier before the third rose to the upper one
+        let resolved = label(spec.with_tie_break(TieBreak::Optimistic), Some(&fine));
+        assert_eq!((resolved.barrier, resolved.resolution), (Barrier::Lower, Resolution::Intrabar));
+        assert!((resolved.realized - (99.0 / 100.2 - 1.0)).abs() < 1e-9);
+
+        // minutes outside of the bar don't count
+        let late = Candles { timestamp: vec![900_000, 960_000, 1_020_000, 1_080_000], ..fine };
+        let assumed = label(spec.with_tie_break(TieBreak::Optimistic), Some(&late));
+        assert_eq!((assumed.barrier, assumed.resolution), (Barrier::Upper, Resolution::Assumed));
+    }
+
+    #[test]
+    fn test_volatility_barriers() {
+        let closes = [100.0, 101.0, 100.0, 101.0, 100.0, 101.0];
+        let candles = candles(&closes.iter().map(|c| (*c, *c, *c, *c)).collect::<Vec<_>>());
+        let vol = volatility(&candles, 3);
+        assert!(vol[3].is_nan());
+        let returns = [101.0 / 100.0 - 1.0, 100.0 / 101.0 - 1.0, 101.0 / 100.0 - 1.0];
+        let mean = returns.iter().sum::<f64>() / 3.0;
+        let std = (returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / 2.0).sqrt();
+        assert!((vol[4] - std).abs() < 1e-12);
+
+        let spec = BarrierSpec::volatility(2.0, 1.0, 1, 3);
+        let (upper, lower) = barriers(&candles, &spec, &vol, 4).unwrap();
+        assert!((upper - 100.0 * (1.0 + 2.0 * std)).abs() < 1e-9);
+        assert!((lower - 100.0 * (1.0 - std)).abs() < 1e-9);
+        assert_eq!(barriers(&candles, &spec, &vol, 2), None);
+    }
+
+    #[test]
+    fn test_triple_barrier_frame() {
+        let df = df![
+            "timestamp" => [0i64, 900_000, 1_800_000],
+            "open" => [100.0, 100.0, 103.0],
+            "high" => [100.5, 103.0, 104.0],
+            "low" => [99.5, 99.9, 102.0],
+            "close" => [100.0, 103.0, 103.5],
+            "volume" => [1.0, 1.0, 1.0],
+            "abnormal volume" => [true, false, false],
+        ].unwrap();
+        let labels = triple_barrier(&df, &BarrierSpec::fixed(0.02, 0.02, 3), "abnormal volume").unwrap();
+        assert_eq!(labels.height(), 1);
+        assert_eq!(
+            Trend::from_series(labels.column("trend from barrier").unwrap()).unwrap(),
+            vec![Some(Trend::Bull)]
+        );
+        assert_eq!(labels.column("bars to touch").unwrap().u32().unwrap().get(0), Some(1));
+    }
+
+    #[test]
+    fn test_triple_barrier_trades() {
+        // the second bar crosses both barriers, the trades within it fell first
+        let df = df![
+            "timestamp" => [0i64, 900_000, 1_800_000],
+            "open" => [100.0, 100.0, 100.0],
+            "high" => [100.5, 103.0, 100.0],
+            "low" => [99.5, 97.5, 100.0],
+            "close" => [100.0, 100.0, 100.0],
+            "volume" => [1.0, 1.0, 1.0],
+            "abnormal volume" => [true, false, false],
+        ].unwrap();
+        let trades = df![
+            "timestamp" => [950_000i64, 1_000_000, 1_100_000],
+            "price" => [99.0, 97.5, 103.0],
+            "qty" => [0.1, 0.2, 0.1],
+        ].unwrap();
+        let spec = BarrierSpec::fixed(0.02, 0.02, 3).with_tie_break(TieBreak::Optimistic);
+        let labels = triple_barrier_intrabar(&df, &trades, &spec, "abnormal volume").unwrap();
+        assert_eq!(
+            Trend::from_series(labels.column("trend from barrier").unwrap()).unwrap(),
+            vec![Some(Trend::Bear)]
+        );
+        let resolution = labels.column("resolution").unwrap().cast(&DataType::Utf8).unwrap();
+        assert_eq!(resolution.utf8().unwrap().get(0), Some(Resolution::Intrabar.name()));
+    }
+}
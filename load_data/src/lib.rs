@@ -1 +1,27 @@
-mod data;
\ No newline at end of file
+pub mod data;
+pub mod pattern;
+pub mod neighbors;
+pub mod dtw;
+pub mod forecast;
+pub mod labeling;
+pub mod meta_labeling;
+pub mod stats;
+pub mod event_study;
+pub mod backtest;
+pub mod engine;
+pub mod costs;
+pub mod margin;
+pub mod metrics;
+pub mod random;
+pub mod signal;
+pub mod sweep;
+pub mod walk_forward;
+pub mod cv;
+pub mod overfitting;
+pub mod robustness;
+pub mod lookahead;
+pub mod synthetic;
+pub mod sizing;
+pub mod portfolio;
+pub mod risk;
+pub mod paper;
@@ -0,0 +1,152 @@
+use polars::prelude::*;
+
+use crate::data::LOOK_AHEAD_COLUMNS;
+
+// Look-ahead detector: a pipeline is run over the whole frame and over prefixes of it, and any column
+// whose values on the prefix change once the later rows are there used the future. The pipeline must
+// keep one output row per input row, e.g. `aggregate`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookAheadSpec {
+    pub cuts: usize,            // prefixes checked, spread over the second half of the rows
+    pub whitelist: Vec<String>, // columns meant to see the future, e.g. labels
+    pub tolerance: f64,
+}
+
+impl LookAheadSpec {
+    pub fn new(cuts: usize) -> Self {
+        Self { cuts, whitelist: Vec::new(), tolerance: 1e-9 }
+    }
+
+    // the group stats and trends of `aggregate` are labels
+    pub fn for_aggregate(cuts: usize) -> Self {
+        Self { whitelist: LOOK_AHEAD_COLUMNS.iter().map(|c| c.to_string()).collect(), ..Self::new(cuts) }
+    }
+
+    fn prefixes(&self, rows: usize) -> Vec<usize> {
+        let mut prefixes = (0..self.cuts).map(|i| rows / 2 + (rows / 2) * i / self.cuts.max(1)).collect::<Vec<_>>();
+        prefixes.retain(|p| *p > 0 && *p < rows);
+        prefixes.dedup();
+        prefixes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub column: String,
+    pub prefix: usize, // rows of the prefix it was run on
+    pub row: usize,    // first row that changed
+    pub full: String,  // value with every row
+    pub truncated: String,
+}
+
+fn same(a: &AnyValue, b: &AnyValue, tolerance: f64) -> bool {
+    let number = |v: &AnyValue| match v {
+        AnyValue::Float64(x) => Some(*x),
+        AnyValue::Float32(x) => Some(*x as f64),
+        AnyValue::Int64(x) => Some(*x as f64),
+        AnyValue::Int32(x) => Some(*x as f64),
+        AnyValue::UInt32(x) => Some(*x as f64),
+        AnyValue::UInt64(x) => Some(*x as f64),
+        _ => None,
+    };
+    match (number(a), number(b)) {
+        (Some(x), Some(y)) => (x.is_nan() && y.is_nan()) || (x - y).abs() <= tolerance * x.abs().max(1f64),
+        _ => a == b,
+    }
+}
+
+// first row of `truncated` differing from `full`
+fn first_change(full: &Series, truncated: &Series, tolerance: f64) -> Result<Option<usize>> {
+    let (full, truncated) = match full.dtype() {
+        DataType::Categorical(_) => (full.cast(&DataType::Utf8)?, truncated.cast(&DataType::Utf8)?),
+        _ => (full.clone(), truncated.clone()),
+    };
+    Ok((0..truncated.len()).find(|i| !same(&full.get(*i), &truncated.get(*i), tolerance)))
+}
+
+pub fn check<F>(df: &DataFrame, pipeline: F, spec: &LookAheadSpec) -> Result<Vec<Finding>>
+where
+    F: Fn(LazyFrame) -> LazyFrame,
+{
+    let full = pipeline(df.clone().lazy()).collect()?;
+    let mut findings: Vec<Finding> = Vec::new();
+    for prefix in spec.prefixes(df.height()) {
+        let truncated = pipeline(df.slice(0, prefix).lazy()).collect()?;
+        for column in truncated.get_columns() {
+            let name = column.name();
+            if spec.whitelist.iter().any(|w| w == name) || findings.iter().any(|f| f.column == name) {
+                continue;
+            }
+            let reference = full.column(name)?.slice(0, truncated.height());
+            if let Some(row) = first_change(&reference, column, spec.tolerance)? {
+                findings.push(Finding {
+                    column: name.to_string(),
+                    prefix,
+                    row,
+                    full: format!("{}", reference.get(row)),
+                    truncated: format!("{}", column.get(row)),
+                });
+            }
+        }
+    }
+    Ok(findings)
+}
+
+pub fn findings_to_frame(findings: &[Finding]) -> Result<DataFrame> {
+    DataFrame::new(vec![
+        Series::new("column", findings.iter().map(|f| f.column.as_str()).collect::<Vec<_>>()),
+        Series::new("prefix", findings.iter().map(|f| f.prefix as u32).collect::<Vec<_>>()),
+        Series::new("row", findings.iter().map(|f| f.row as u32).collect::<Vec<_>>()),
+        Series::new("full", findings.iter().map(|f| f.full.as_str()).collect::<Vec<_>>()),
+        Series::new("truncated", findings.iter().map(|f| f.truncated.as_str()).collect::<Vec<_>>()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::aggregate;
+
+    fn candles() -> DataFrame {
+        let n = 120;
+        let close = (0..n).map(|i| 100.0 + 2.0 * (i as f64 / 5.0).sin() + 0.01 * i as f64).collect::<Vec<_>>();
+        df![
+            "openTime" => (0..n as i64).map(|i| i * 900_000).collect::<Vec<_>>(),
+            "open" => close.clone(),
+            "high" => close.iter().enumerate().map(|(i, c)| c + 0.5 + (i % 7) as f64 * 0.3).collect::<Vec<_>>(),
+            "low" => close.iter().enumerate().map(|(i, c)| c - 0.5 - (i % 5) as f64 * 0.3).collect::<Vec<_>>(),
+            "close" => close,
+            "volume" => (0..n).map(|i| if i % 17 == 0 { 9.0 } else { 1.0 + (i % 4) as f64 * 0.2 }).collect::<Vec<_>>(),
+        ].unwrap()
+    }
+
+    #[test]
+    fn test_causal_pipeline() {
+        let pipeline = |lf| aggregate(lf, 1.5, 0.01, 10);
+        assert_eq!(check(&candles(), pipeline, &LookAheadSpec::for_aggregate(4)).unwrap(), vec![]);
+
+        // without the whitelist the group stats are caught
+        let findings = check(&candles(), pipeline, &LookAheadSpec::new(4)).unwrap();
+        let columns = findings.iter().map(|f| f.column.as_str()).collect::<Vec<_>>();
+        assert!(columns.contains(&"max high for duration"));
+        assert!(columns.iter().all(|c| LOOK_AHEAD_COLUMNS.contains(c)));
+    }
+
+    #[test]
+    fn test_forgotten_shift() {
+        let pipeline = |lf: LazyFrame| {
+            lf.with_columns([
+                col("close").rolling_mean(RollingOptions { window_size: Duration::new(3), min_periods: 3, ..Default::default() }).alias("causal"),
+                col("close").shift(-1).alias("next close"),
+                (col("volume") / col("volume").mean()).alias("relative volume"),
+            ])
+        };
+        let findings = check(&candles(), pipeline, &LookAheadSpec::new(3)).unwrap();
+        let columns = findings.iter().map(|f| f.column.as_str()).collect::<Vec<_>>();
+        assert_eq!(columns, vec!["next close", "relative volume"]);
+        // the last row of the prefix has no next close
+        assert_eq!(findings[0].row, findings[0].prefix - 1);
+        assert_eq!(findings_to_frame(&findings).unwrap().height(), 2);
+    }
+}
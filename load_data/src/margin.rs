@@ -0,0 +1,142 @@
+use crate::engine::{Account, Side};
+
+// Margin of a USDT-M perpetual position in one-way mode: initial margin from the leverage,
+// maintenance margin from the notional brackets and the liquidation price against the mark price.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarginMode {
+    Isolated, // only the initial margin of the position is at risk
+    Cross,    // the whole wallet backs the position
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bracket {
+    pub notional: f64, // upper bound of the notional of the bracket
+    pub max_leverage: f64,
+    pub rate: f64,   // maintenance margin rate
+    pub amount: f64, // maintenance amount, keeps the margin continuous across brackets
+}
+
+// leverage brackets of BTCUSDT
+pub const BTCUSDT_BRACKETS: [Bracket; 10] = [
+    Bracket { notional: 50e3, max_leverage: 125.0, rate: 0.004, amount: 0.0 },
+    Bracket { notional: 250e3, max_leverage: 100.0, rate: 0.005, amount: 50.0 },
+    Bracket { notional: 1e6, max_leverage: 50.0, rate: 0.01, amount: 1_300.0 },
+    Bracket { notional: 10e6, max_leverage: 20.0, rate: 0.025, amount: 16_300.0 },
+    Bracket { notional: 20e6, max_leverage: 10.0, rate: 0.05, amount: 266_300.0 },
+    Bracket { notional: 50e6, max_leverage: 5.0, rate: 0.1, amount: 1_266_300.0 },
+    Bracket { notional: 100e6, max_leverage: 4.0, rate: 0.125, amount: 2_516_300.0 },
+    Bracket { notional: 200e6, max_leverage: 3.0, rate: 0.15, amount: 5_016_300.0 },
+    Bracket { notional: 300e6, max_leverage: 2.0, rate: 0.25, amount: 25_016_300.0 },
+    Bracket { notional: 500e6, max_leverage: 1.0, rate: 0.5, amount: 100_016_300.0 },
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarginSpec {
+    pub mode: MarginMode,
+    pub leverage: f64,
+    pub brackets: Vec<Bracket>,
+}
+
+impl MarginSpec {
+    pub fn new(mode: MarginMode, leverage: f64) -> Self {
+        Self { mode, leverage, brackets: BTCUSDT_BRACKETS.to_vec() }
+    }
+
+    pub fn bracket(&self, notional: f64) -> &Bracket {
+        let last = self.brackets.len() - 1;
+        &self.brackets[self.brackets.iter().position(|b| notional <= b.notional).unwrap_or(last)]
+    }
+
+    pub fn initial(&self, notional: f64) -> f64 {
+        notional.abs() / self.leverage
+    }
+
+    pub fn maintenance(&self, notional: f64) -> f64 {
+        let bracket = self.bracket(notional.abs());
+        (notional.abs() * bracket.rate - bracket.amount).max(0f64)
+    }
+
+    // the balance backing the position: the initial margin at the entry price when isolated
+    pub fn balance(&self, account: &Account) -> f64 {
+        match self.mode {
+            MarginMode::Isolated => self.initial(account.position * account.entry_price),
+            MarginMode::Cross => account.wallet(),
+        }
+    }
+
+    // Mark price at which the balance plus the unrealized pnl falls to the maintenance margin,
+    // with the bracket of the notional at the entry price.
+    pub fn liquidation_price(&self, account: &Account) -> Option<f64> {
+        if account.position == 0f64 {
+            return None;
+        }
+        let (size, side) = (account.position.abs(), account.position.signum());
+        let bracket = self.bracket(size * account.entry_price);
+        let price = (self.balance(account) + bracket.amount - side * size * account.entry_price)
+            / (size * bracket.rate - side * size);
+        Some(price.max(0f64))
+    }
+
+    // Whether a fill fits the leverage: increasing the exposure needs the initial margin of the
+    // resulting position out of the equity, within the max leverage of its bracket.
+    pub fn allows(&self, account: &Account, side: Side, quantity: f64, price: f64) -> bool {
+        let position = account.position + side.sign() * quantity;
+        if position.abs() <= account.position.abs() && position.signum() * account.position.signum() >= 0f64 {
+            return true;
+        }
+        let notional = position.abs() * price;
+        self.leverage <= self.bracket(notional).max_leverage && self.initial(notional) <= account.equity(price) + 1e-9
+    }
+}
+
+// whether the adverse mark price of a bar reached the liquidation price of the position
+pub fn liquidated(position: f64, liquidation_price: f64, mark_low: f64, mark_high: f64) -> bool {
+    if position > 0f64 {
+        mark_low <= liquidation_price
+    } else {
+        mark_high >= liquidation_price
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_liquidation_price() {
+        // 1 BTC long at 20000 with 10x isolated: 2000 of margin, 0.4% maintenance
+        let mut account = Account::new(10_000.0);
+        account.apply(Side::Buy, 1.0, 20_000.0);
+        let isolated = MarginSpec::new(MarginMode::Isolated, 10.0);
+        let price = isolated.liquidation_price(&account).unwrap();
+        assert!((price - (20_000.0 - 2_000.0) / (1.0 - 0.004)).abs() < 1e-9);
+        // at the liquidation price the margin left is the maintenance margin
+        assert!((2_000.0 + account.unrealized(price) - isolated.maintenance(price)).abs() < 1e-6);
+
+        // the whole wallet backs a cross position
+        let cross = MarginSpec::new(MarginMode::Cross, 10.0);
+        assert!(cross.liquidation_price(&account).unwrap() < price);
+
+        let mut short = Account::new(10_000.0);
+        short.apply(Side::Sell, 1.0, 20_000.0);
+        assert!((isolated.liquidation_price(&short).unwrap() - 22_000.0 / 1.004).abs() < 1e-9);
+        assert!(liquidated(-1.0, 21_900.0, 21_000.0, 21_950.0));
+        assert!(!liquidated(1.0, 18_000.0, 18_100.0, 21_950.0));
+    }
+
+    #[test]
+    fn test_brackets_and_leverage() {
+        let spec = MarginSpec::new(MarginMode::Cross, 20.0);
+        assert!((spec.maintenance(100e3) - (100e3 * 0.005 - 50.0)).abs() < 1e-9);
+        // continuous across the brackets
+        assert!((spec.maintenance(250e3) - (250e3 * 0.01 - 1_300.0)).abs() < 1e-9);
+
+        let account = Account::new(1_000.0);
+        assert!(spec.allows(&account, Side::Buy, 1.0, 20_000.0));
+        assert!(!spec.allows(&account, Side::Buy, 1.1, 20_000.0));
+        // 20x is over the max leverage of positions above 10M
+        let whale = Account::new(1e6);
+        assert!(!spec.allows(&whale, Side::Sell, 600.0, 20_000.0));
+    }
+}
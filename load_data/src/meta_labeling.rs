@@ -0,0 +1,193 @@
+use polars::prelude::*;
+
+use crate::data::{bool_column, signal, timestamp_series, Candles};
+use crate::labeling::{label_event_with, volatility, BarrierSpec, Label};
+
+// Meta-labeling of the primary signal `abnormal volume AND (upper XOR lower band touched)`:
+// the primary signal picks the side, the secondary model learns whether to take the trade.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandSide {
+    Momentum,  // long on the upper band, short on the lower band
+    Reversion, // short on the upper band, long on the lower band
+}
+
+impl BandSide {
+    pub fn side(&self, upper_touched: bool) -> f64 {
+        match (self, upper_touched) {
+            (BandSide::Momentum, true) | (BandSide::Reversion, false) => 1f64,
+            _ => -1f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlap {
+    Keep, // every event is labeled, weigh them by uniqueness
+    Skip, // events while a previous trade is still open are dropped
+}
+
+#[derive(Debug, Clone)]
+pub struct MetaSpec {
+    pub band_side: BandSide,
+    // barriers of a long trade, upper for the profit taking and lower for the stop,
+    // mirrored for short trades
+    pub barriers: BarrierSpec,
+    pub overlap: Overlap,
+    pub features: Vec<String>, // columns known at the open of the event bar
+}
+
+impl MetaSpec {
+    pub fn new(band_side: BandSide, barriers: BarrierSpec) -> Self {
+        Self {
+            band_side,
+            barriers,
+            overlap: Overlap::Keep,
+            features: ["mean volume", "std volume", "mean high", "std high", "mean low", "std low"]
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+        }
+    }
+
+    fn barriers_for(&self, side: f64) -> BarrierSpec {
+        if side > 0f64 {
+            self.barriers
+        } else {
+            BarrierSpec {
+                upper: self.barriers.lower,
+                lower: self.barriers.upper,
+                tie_break: self.barriers.tie_break.for_short(),
+                ..self.barriers
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetaLabel {
+    pub side: f64,
+    pub label: Label,
+    pub realized: f64, // return of the trade in the direction of the side
+}
+
+impl MetaLabel {
+    pub fn profitable(&self) -> bool {
+        self.realized > 0f64
+    }
+}
+
+// Share of each bar an event "owns": 1 / number of events alive at the bar, averaged over its lifespan.
+pub fn uniqueness(spans: &[(usize, usize)], bars: usize) -> Vec<f64> {
+    let mut concurrency = vec![0u32; bars];
+    for &(start, end) in spans {
+        concurrency[start..=end].iter_mut().for_each(|c| *c += 1);
+    }
+    spans
+        .iter()
+        .map(|&(start, end)| {
+            concurrency[start..=end].iter().map(|c| 1f64 / *c as f64).sum::<f64>() / (end - start + 1) as f64
+        })
+        .collect()
+}
+
+pub fn meta_label_events(candles: &Candles, events: &[bool], upper_touched: &[bool], spec: &MetaSpec) -> Vec<MetaLabel> {
+    let vol = volatility(candles, spec.barriers.vol_window);
+    let mut labels: Vec<MetaLabel> = Vec::new();
+    for (event, _) in events.iter().enumerate().filter(|(_, e)| **e) {
+        if spec.overlap == Overlap::Skip && labels.last().map(|l| event <= l.label.touch).unwrap_or(false) {
+            continue;
+        }
+        let side = spec.band_side.side(upper_touched[event]);
+        if let Some(label) = label_event_with(candles, None, &spec.barriers_for(side), &vol, event) {
+            let realized = side * label.realized;
+            labels.push(MetaLabel { side, label, realized });
+        }
+    }
+    labels
+}
+
+// Features at each event of the primary signal with the binary label and sample weights
+// for a secondary model. `df` is the output of `aggregate`.
+pub fn meta_labels(df: &DataFrame, spec: &MetaSpec) -> Result<DataFrame> {
+    let candles = Candles::from_frame(df)?;
+    let flags = df
+        .clone()
+        .lazy()
+        .select([signal().alias("signal"), col("upper band touched")])
+        .collect()?;
+    let events = bool_column(&flags, "signal")?;
+    let upper_touched = bool_column(&flags, "upper band touched")?;
+
+    let labels = meta_label_events(&candles, &events, &upper_touched, spec);
+    let spans = labels.iter().map(|l| (l.label.event, l.label.touch)).collect::<Vec<_>>();
+    let weights = uniqueness(&spans, candles.len());
+
+    let rows = IdxCa::from_vec("event", labels.iter().map(|l| l.label.event as IdxSize).collect());
+    let mut table = DataFrame::new(vec![
+        timestamp_series("timestamp", labels.iter().map(|l| candles.timestamp[l.label.event]).collect())?,
+        Series::new("side", labels.iter().map(|l| l.side).collect::<Vec<_>>()),
+    ])?;
+    table.hstack_mut(df.select(&spec.features)?.take(&rows)?.get_columns())?;
+    table.hstack_mut(&[
+        timestamp_series("touch timestamp", labels.iter().map(|l| candles.timestamp[l.label.touch]).collect())?,
+        Series::new("barrier", labels.iter().map(|l| l.label.barrier.name()).collect::<Vec<_>>())
+            .cast(&DataType::Categorical(None))?,
+        Series::new("realized return", labels.iter().map(|l| l.realized).collect::<Vec<_>>()),
+        Series::new("label", labels.iter().map(|l| l.profitable() as u32).collect::<Vec<_>>()),
+        Series::new("uniqueness", weights),
+    ])?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniqueness() {
+        // bars 0..=3 and 2..=5 overlap on two bars
+        let weights = uniqueness(&[(0, 3), (2, 5), (7, 7)], 8);
+        assert!((weights[0] - (1.0 + 1.0 + 0.5 + 0.5) / 4.0).abs() < 1e-12);
+        assert!((weights[1] - (0.5 + 0.5 + 1.0 + 1.0) / 4.0).abs() < 1e-12);
+        assert_eq!(weights[2], 1.0);
+    }
+
+    #[test]
+    fn test_meta_labels() {
+        let df = df![
+            "timestamp" => (0..6i64).map(|i| i * 900_000).collect::<Vec<_>>(),
+            "open" => [100.0, 100.0, 101.0, 99.0, 98.0, 98.0],
+            "high" => [100.5, 101.5, 101.0, 99.0, 98.5, 98.0],
+            "low" => [99.5, 99.8, 98.5, 97.5, 97.9, 98.0],
+            "close" => [100.0, 101.0, 99.0, 98.0, 98.0, 98.0],
+            "volume" => [1.0; 6],
+            "abnormal volume" => [true, false, true, false, false, false],
+            "upper band touched" => [true, false, false, false, false, false],
+            "lower band touched" => [false, false, true, false, false, false],
+            "mean volume" => [1.0, 1.0, 2.0, 1.0, 1.0, 1.0],
+        ].unwrap();
+        let barriers = BarrierSpec::fixed(0.01, 0.02, 4);
+        let mut spec = MetaSpec::new(BandSide::Momentum, barriers);
+        spec.features = vec!["mean volume".into()];
+
+        let table = meta_labels(&df, &spec).unwrap();
+        assert_eq!(table.height(), 2);
+        let get = |name: &str| table.column(name).unwrap().f64().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        // long on the upper band takes profit at 101, short on the lower band takes profit at 99.99
+        assert_eq!(get("side"), vec![1.0, -1.0]);
+        assert!((get("realized return")[0] - 0.01).abs() < 1e-12);
+        assert!((get("realized return")[1] - 0.01).abs() < 1e-12);
+        assert_eq!(get("mean volume"), vec![1.0, 2.0]);
+        assert_eq!(table.column("label").unwrap().u32().unwrap().into_no_null_iter().collect::<Vec<_>>(), vec![1, 1]);
+
+        // a reversion trader shorts the first into the drop and is stopped out of the long on the second
+        let spec = MetaSpec { band_side: BandSide::Reversion, ..spec };
+        let table = meta_labels(&df, &spec).unwrap();
+        assert_eq!(table.column("label").unwrap().u32().unwrap().into_no_null_iter().collect::<Vec<_>>(), vec![1, 0]);
+
+        // the second event fires while the first trade is still open
+        let spec = MetaSpec { overlap: Overlap::Skip, ..spec };
+        assert_eq!(meta_labels(&df, &spec).unwrap().height(), 1);
+    }
+}
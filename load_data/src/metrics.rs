@@ -0,0 +1,232 @@
+use polars::prelude::*;
+
+use crate::data::{f64_column, Interval};
+use crate::stats;
+
+// Performance of a backtest from its bar returns, annualized by the interval of the bars.
+// The risk free rate is taken as zero.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub interval: Interval,
+    pub bars: usize,
+    pub total_return: f64,
+    pub annual_return: f64, // compounded
+    pub annual_volatility: f64,
+    pub sharpe: f64,
+    pub sortino: f64,
+    pub calmar: f64,
+    pub information_ratio: Option<f64>, // against the benchmark
+    pub max_drawdown: f64,
+    pub max_drawdown_bars: usize, // longest stretch under a previous peak
+    pub trades: usize,
+    pub hit_rate: f64,
+    pub profit_factor: f64,
+    pub average_trade: f64,
+    pub turnover: f64, // annualized
+    pub exposure: f64, // share of the bars with a position
+}
+
+pub fn equity(returns: &[f64]) -> Vec<f64> {
+    returns.iter().scan(1f64, |e, r| { *e *= 1f64 + r; Some(*e) }).collect()
+}
+
+// relative drawdown from the running peak, starting from an equity of 1
+pub fn drawdown(equity: &[f64]) -> Vec<f64> {
+    let mut peak = 1f64;
+    equity
+        .iter()
+        .map(|e| {
+            peak = peak.max(*e);
+            e / peak - 1f64
+        })
+        .collect()
+}
+
+fn longest_underwater(drawdown: &[f64]) -> usize {
+    let (mut longest, mut current) = (0, 0);
+    for dd in drawdown {
+        current = if *dd < 0f64 { current + 1 } else { 0 };
+        longest = longest.max(current);
+    }
+    longest
+}
+
+// compounded returns of the round trips, a trade lasting while the position keeps its sign
+pub fn round_trips(position: &[f64], returns: &[f64]) -> Vec<f64> {
+    let mut trips = Vec::new();
+    let mut open: Option<(f64, f64)> = None; // side and compounded return
+    for (p, r) in position.iter().zip(returns) {
+        let side = if *p > 0f64 { 1f64 } else if *p < 0f64 { -1f64 } else { 0f64 };
+        if let Some((open_side, growth)) = open {
+            if side != open_side {
+                trips.push(growth - 1f64);
+                open = None;
+            }
+        }
+        if side != 0f64 {
+            let (_, growth) = open.get_or_insert((side, 1f64));
+            *growth *= 1f64 + r;
+        }
+    }
+    if let Some((_, growth)) = open {
+        trips.push(growth - 1f64);
+    }
+    trips
+}
+
+pub fn metrics(returns: &[f64], position: &[f64], turnover: &[f64], benchmark: Option<&[f64]>, interval: Interval) -> Metrics {
+    let returns = returns.iter().map(|r| if r.is_finite() { *r } else { 0f64 }).collect::<Vec<_>>();
+    let periods = interval.periods_per_year();
+    let bars = returns.len();
+    let equity = equity(&returns);
+    let drawdown = drawdown(&equity);
+    let total_return = equity.last().map(|e| e - 1f64).unwrap_or(0f64);
+    let annual_return = (1f64 + total_return).powf(periods / bars as f64) - 1f64;
+
+    let mean = stats::mean(&returns);
+    let std = stats::std(&returns);
+    let downside = (returns.iter().map(|r| r.min(0f64).powi(2)).sum::<f64>() / bars as f64).sqrt();
+    let max_drawdown = drawdown.iter().copied().fold(0f64, f64::min);
+
+    let information_ratio = benchmark.map(|benchmark| {
+        let active = returns
+            .iter()
+            .zip(benchmark)
+            .map(|(r, b)| r - if b.is_finite() { *b } else { 0f64 })
+            .collect::<Vec<_>>();
+        stats::mean(&active) / stats::std(&active) * periods.sqrt()
+    });
+
+    let trips = round_trips(position, &returns);
+    let gains = trips.iter().filter(|t| **t > 0f64).sum::<f64>();
+    let losses = -trips.iter().filter(|t| **t < 0f64).sum::<f64>();
+
+    Metrics {
+        interval,
+        bars,
+        total_return,
+        annual_return,
+        annual_volatility: std * periods.sqrt(),
+        sharpe: mean / std * periods.sqrt(),
+        sortino: mean / downside * periods.sqrt(),
+        calmar: annual_return / -max_drawdown,
+        information_ratio,
+        max_drawdown,
+        max_drawdown_bars: longest_underwater(&drawdown),
+        trades: trips.len(),
+        hit_rate: trips.iter().filter(|t| **t > 0f64).count() as f64 / trips.len() as f64,
+        profit_factor: gains / losses,
+        average_trade: stats::mean(&trips),
+        turnover: stats::mean(turnover) * periods,
+        exposure: position.iter().filter(|p| **p != 0f64).count() as f64 / bars as f64,
+    }
+}
+
+fn first_column(df: &DataFrame, names: &[&str]) -> Option<String> {
+    names.iter().find(|name| df.column(name).is_ok()).map(|name| name.to_string())
+}
+
+// Metrics of the output of `backtest` (after costs if `with_costs` was run) or of the equity frame of
+// the engine, where the position is a quantity and the turnover is counted in it.
+pub fn metrics_from_frame(df: &DataFrame, interval: Interval, benchmark: Option<&str>) -> Result<Metrics> {
+    let returns = match first_column(df, &["net return", "strategy return"]) {
+        Some(column) => f64_column(df, &column)?,
+        None => {
+            let equity = f64_column(df, "equity")?;
+            (0..equity.len()).map(|i| if i == 0 { 0f64 } else { equity[i] / equity[i - 1] - 1f64 }).collect()
+        }
+    };
+    let position = match first_column(df, &["held position", "position"]) {
+        Some(column) => f64_column(df, &column)?,
+        None => vec![0f64; df.height()],
+    };
+    let turnover = match df.column("turnover") {
+        Ok(_) => f64_column(df, "turnover")?,
+        Err(_) => (0..position.len()).map(|i| (position[i] - if i == 0 { 0f64 } else { position[i - 1] }).abs()).collect(),
+    };
+    let benchmark = benchmark.map(|column| f64_column(df, column)).transpose()?;
+    Ok(metrics(&returns, &position, &turnover, benchmark.as_deref(), interval))
+}
+
+fn percent(x: f64) -> String {
+    format!("{:.2}%", x * 100f64)
+}
+
+impl Metrics {
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let days = self.max_drawdown_bars as f64 * self.interval.minutes() as f64 / (24f64 * 60f64);
+        vec![
+            ("bars", format!("{} x {}", self.bars, self.interval)),
+            ("total return", percent(self.total_return)),
+            ("annual return", percent(self.annual_return)),
+            ("annual volatility", percent(self.annual_volatility)),
+            ("sharpe", format!("{:.2}", self.sharpe)),
+            ("sortino", format!("{:.2}", self.sortino)),
+            ("calmar", format!("{:.2}", self.calmar)),
+            ("information ratio", self.information_ratio.map(|ir| format!("{:.2}", ir)).unwrap_or_else(|| "-".into())),
+            ("max drawdown", percent(self.max_drawdown)),
+            ("max drawdown duration", format!("{} bars ({:.1} days)", self.max_drawdown_bars, days)),
+            ("trades", self.trades.to_string()),
+            ("hit rate", percent(self.hit_rate)),
+            ("profit factor", format!("{:.2}", self.profit_factor)),
+            ("average trade", percent(self.average_trade)),
+            ("turnover", format!("{:.1}x a year", self.turnover)),
+            ("exposure", percent(self.exposure)),
+        ]
+    }
+
+    pub fn tearsheet(&self) -> String {
+        let mut sheet = String::from("| metric | value |\n|---|---:|\n");
+        for (name, value) in self.rows() {
+            sheet.push_str(&format!("| {} | {} |\n", name, value));
+        }
+        sheet
+    }
+}
+
+impl std::fmt::Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, value) in self.rows() {
+            writeln!(f, "{:<24}{:>24}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let returns = [0.0, 0.02, -0.01, -0.01, 0.03, 0.0, -0.02, 0.01];
+        let position = [0.0, 1.0, 1.0, 1.0, 1.0, 0.0, -1.0, -1.0];
+        let turnover = [0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0];
+        let benchmark = [0.0; 8];
+        let m = metrics(&returns, &position, &turnover, Some(&benchmark), Interval::D1);
+
+        let growth = 1.02 * 0.99 * 0.99 * 1.03 * 0.98 * 1.01;
+        assert!((m.total_return - (growth - 1.0)).abs() < 1e-12);
+        assert!((m.annual_return - (growth.powf(365.0 / 8.0) - 1.0)).abs() < 1e-9);
+        assert!((m.sharpe - stats::mean(&returns) / stats::std(&returns) * 365f64.sqrt()).abs() < 1e-9);
+        // against a flat benchmark the information ratio is the sharpe
+        assert!((m.information_ratio.unwrap() - m.sharpe).abs() < 1e-9);
+        // two bars under the peak of 1.02, then the short loses 2% from the next peak
+        assert!((m.max_drawdown + 0.02).abs() < 1e-12);
+        assert_eq!(m.max_drawdown_bars, 2);
+        assert_eq!(m.trades, 2);
+        assert_eq!(m.hit_rate, 0.5);
+        assert!((m.profit_factor - (1.02 * 0.99 * 0.99 * 1.03 - 1.0) / (1.0 - 0.98 * 1.01)).abs() < 1e-12);
+        assert!((m.turnover - 3.0 / 8.0 * 365.0).abs() < 1e-9);
+        assert_eq!(m.exposure, 6.0 / 8.0);
+        assert!(m.tearsheet().contains("| max drawdown duration | 2 bars (2.0 days) |"));
+    }
+
+    #[test]
+    fn test_interval() {
+        assert_eq!("15m".parse::<Interval>().unwrap(), Interval::M15);
+        assert_eq!(Interval::M15.periods_per_year(), 35_040.0);
+        assert_eq!(Interval::H8.millis(), 8 * 3_600_000);
+    }
+}
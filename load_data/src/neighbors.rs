@@ -0,0 +1,376 @@
+use polars::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::pattern::{matches_to_frame, Match, PatternSpec, Windows};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Cosine, // angular distance between unit embeddings, a metric unlike 1 - cos
+    L1,
+}
+
+impl Metric {
+    // Weighted windows are embedded so that the plain metric equals the weighted one:
+    // sqrt(w) for the weighted cosine similarity of the canvas, w for the weighted L1 norm.
+    pub fn embed(&self, window: &[f64], weights: &[f64]) -> Vec<f64> {
+        match self {
+            Metric::Cosine => {
+                let e = window.iter().zip(weights).map(|(x, w)| x * w.sqrt()).collect::<Vec<_>>();
+                let norm = e.iter().map(|x| x * x).sum::<f64>().sqrt();
+                if norm == 0f64 {
+                    e
+                } else {
+                    e.into_iter().map(|x| x / norm).collect()
+                }
+            }
+            Metric::L1 => window.iter().zip(weights).map(|(x, w)| x * w).collect(),
+        }
+    }
+
+    pub fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        match self {
+            Metric::Cosine => {
+                let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+                dot.clamp(-1f64, 1f64).acos()
+            }
+            Metric::L1 => a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum(),
+        }
+    }
+
+    // higher is more similar, so that matches of both metrics rank the same way
+    pub fn similarity(&self, distance: f64) -> f64 {
+        match self {
+            Metric::Cosine => distance.cos(),
+            Metric::L1 => -distance,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Neighbor {
+    pub id: usize,
+    pub distance: f64,
+}
+
+impl Eq for Neighbor {}
+
+impl Ord for Neighbor {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.partial_cmp(&other.distance).unwrap_or(Ordering::Equal).then(self.id.cmp(&other.id))
+    }
+}
+
+impl PartialOrd for Neighbor {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+enum Node {
+    // split once it holds more than `limit`, doubled when a split finds nothing to prune on
+    Leaf { slots: Vec<usize>, limit: usize },
+    Branch {
+        vantage: usize,
+        radius: f64,
+        inside: Box<Node>,  // distance to the vantage point < radius
+        outside: Box<Node>, // distance to the vantage point >= radius
+    },
+}
+
+// Vantage point tree accepting points one by one as new candles arrive.
+// A point descends to a leaf by the same rule branches were split with, so searches stay exact.
+pub struct VpTree {
+    metric: Metric,
+    leaf_size: usize,
+    ids: Vec<usize>,
+    points: Vec<Vec<f64>>,
+    root: Node,
+}
+
+impl VpTree {
+    pub fn new(metric: Metric) -> Self {
+        Self::with_leaf_size(metric, 32)
+    }
+
+    pub fn with_leaf_size(metric: Metric, leaf_size: usize) -> Self {
+        Self {
+            metric,
+            leaf_size: leaf_size.max(2),
+            ids: Vec::new(),
+            points: Vec::new(),
+            root: Node::Leaf { slots: Vec::new(), limit: leaf_size.max(2) },
+        }
+    }
+
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn insert(&mut self, id: usize, point: Vec<f64>) {
+        let slot = self.points.len();
+        self.ids.push(id);
+        self.points.push(point);
+
+        let mut node = &mut self.root;
+        loop {
+            match node {
+                Node::Branch { vantage, radius, inside, outside } => {
+                    let d = self.metric.distance(&self.points[*vantage], &self.points[slot]);
+                    node = if d < *radius { inside } else { outside };
+                }
+                Node::Leaf { slots, limit } => {
+                    slots.push(slot);
+                    if slots.len() > *limit {
+                        let (slots, limit) = (std::mem::take(slots), *limit);
+                        *node = split(self.metric, &self.points, slots, limit, self.leaf_size);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    pub fn nearest(&self, query: &[f64], k: usize) -> Vec<Neighbor> {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        if k > 0 {
+            self.search(&self.root, query, k, &mut heap);
+        }
+        let mut neighbors = heap.into_vec();
+        neighbors.sort();
+        neighbors
+    }
+
+    fn search(&self, node: &Node, query: &[f64], k: usize, heap: &mut BinaryHeap<Neighbor>) {
+        match node {
+            Node::Leaf { slots, .. } => {
+                for &slot in slots {
+                    self.offer(slot, self.metric.distance(query, &self.points[slot]), k, heap);
+                }
+            }
+            Node::Branch { vantage, radius, inside, outside } => {
+                let d = self.metric.distance(query, &self.points[*vantage]);
+                self.offer(*vantage, d, k, heap);
+                let (near, far) = if d < *radius { (inside, outside) } else { (outside, inside) };
+                self.search(near, query, k, heap);
+                if (d - radius).abs() <= tau(heap, k) {
+                    self.search(far, query, k, heap);
+                }
+            }
+        }
+    }
+
+    fn offer(&self, slot: usize, distance: f64, k: usize, heap: &mut BinaryHeap<Neighbor>) {
+        if heap.len() < k || distance < tau(heap, k) {
+            heap.push(Neighbor { id: self.ids[slot], distance });
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+    }
+}
+
+fn tau(heap: &BinaryHeap<Neighbor>, k: usize) -> f64 {
+    if heap.len() < k {
+        f64::INFINITY
+    } else {
+        heap.peek().map(|n| n.distance).unwrap_or(f64::INFINITY)
+    }
+}
+
+fn split(metric: Metric, points: &[Vec<f64>], slots: Vec<usize>, limit: usize, leaf_size: usize) -> Node {
+    let vantage = slots[0];
+    let mut others = slots[1..]
+        .iter()
+        .map(|&slot| (metric.distance(&points[vantage], &points[slot]), slot))
+        .collect::<Vec<_>>();
+    others.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    let radius = others[others.len() / 2].0;
+
+    let (inside, outside): (Vec<_>, Vec<_>) = others.into_iter().partition(|(d, _)| *d < radius);
+    if inside.is_empty() {
+        // at least half the points are as near as they can be to the vantage point, e.g. duplicate
+        // windows, nothing to prune on until the leaf has grown
+        return Node::Leaf { slots, limit: 2 * limit };
+    }
+    let leaf = |half: Vec<(f64, usize)>| {
+        Box::new(Node::Leaf { slots: half.into_iter().map(|(_, slot)| slot).collect(), limit: leaf_size })
+    };
+    Node::Branch { vantage, radius, inside: leaf(inside), outside: leaf(outside) }
+}
+
+pub fn brute_force<'a, I>(metric: Metric, points: I, query: &[f64], k: usize) -> Vec<Neighbor>
+    where
+        I: IntoIterator<Item = (usize, &'a Vec<f64>)>
+{
+    let mut neighbors = points
+        .into_iter()
+        .map(|(id, point)| Neighbor { id, distance: metric.distance(query, point) })
+        .collect::<Vec<_>>();
+    neighbors.sort();
+    neighbors.truncate(k);
+    neighbors
+}
+
+// Windows of a frame indexed as they become eligible, mirroring a live feed:
+// at row t the window ending at t - exclusion is inserted, then the window ending at t is queried.
+pub struct PatternIndex {
+    spec: PatternSpec,
+    weights: Vec<f64>,
+    tree: VpTree,
+}
+
+impl PatternIndex {
+    pub fn new(spec: PatternSpec, metric: Metric) -> Self {
+        let weights = spec.weights();
+        Self { spec, weights, tree: VpTree::new(metric) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn insert(&mut self, index: usize, window: &[f64]) {
+        let embedding = self.tree.metric().embed(window, &self.weights);
+        self.tree.insert(index, embedding);
+    }
+
+    pub fn query(&self, query: usize, window: &[f64]) -> Vec<Match> {
+        let metric = self.tree.metric();
+        self.tree
+            .nearest(&metric.embed(window, &self.weights), self.spec.top_k)
+            .into_iter()
+            .map(|n| Match { query, index: n.id, similarity: metric.similarity(n.distance) })
+            .collect()
+    }
+
+    // feed row `t`, returning its matches against every window known by then
+    pub fn advance(&mut self, windows: &Windows, t: usize) -> Vec<Match> {
+        if let Some(eligible) = t.checked_sub(self.spec.exclusion()) {
+            if let Some(window) = windows.get(eligible) {
+                self.insert(eligible, &window);
+            }
+        }
+        match windows.get(t) {
+            Some(window) => self.query(t, &window),
+            None => Vec::new(),
+        }
+    }
+}
+
+// Same output as `pattern::similar_patterns` for every row, answered from the index.
+pub fn similar_patterns_indexed(df: &DataFrame, spec: &PatternSpec, metric: Metric) -> Result<DataFrame> {
+    let windows = Windows::new(df, spec)?;
+    let mut index = PatternIndex::new(spec.clone(), metric);
+    let matches = (0..windows.len()).flat_map(|t| index.advance(&windows, t)).collect::<Vec<_>>();
+    matches_to_frame(df, spec, &matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{find_similar, mean_scaling};
+
+    fn random_points(n: usize, dim: usize, seed: u64) -> Vec<Vec<f64>> {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+        };
+        (0..n).map(|_| (0..dim).map(|_| next()).collect()).collect()
+    }
+
+    fn recall(found: &[Neighbor], exact: &[Neighbor]) -> f64 {
+        let hits = exact.iter().filter(|e| found.iter().any(|f| f.id == e.id)).count();
+        hits as f64 / exact.len() as f64
+    }
+
+    #[test]
+    fn test_recall_against_brute_force() {
+        let points = random_points(2000, 12, 7);
+        let queries = random_points(50, 12, 11);
+        for metric in [Metric::Cosine, Metric::L1] {
+            let weights = vec![1f64; 12];
+            let points = points.iter().map(|p| metric.embed(p, &weights)).collect::<Vec<_>>();
+            let mut tree = VpTree::with_leaf_size(metric, 8);
+            for (id, point) in points.iter().enumerate() {
+                tree.insert(id, point.clone());
+            }
+            assert_eq!(tree.len(), points.len());
+
+            let total = queries
+                .iter()
+                .map(|q| {
+                    let q = metric.embed(q, &weights);
+                    let exact = brute_force(metric, points.iter().enumerate(), &q, 10);
+                    recall(&tree.nearest(&q, 10), &exact)
+                })
+                .sum::<f64>();
+            assert!((total / queries.len() as f64 - 1f64).abs() < 1e-12, "{:?}", metric);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_points() {
+        // flat windows: the leaf only retries splitting each time it doubles
+        let mut tree = VpTree::with_leaf_size(Metric::L1, 8);
+        for id in 0..1000 {
+            tree.insert(id, vec![1f64; 6]);
+        }
+        match &tree.root {
+            Node::Leaf { slots, limit } => assert!(slots.len() == 1000 && *limit == 8 << 7),
+            Node::Branch { .. } => panic!("duplicates split"),
+        }
+        let found = tree.nearest(&[1f64; 6], 5);
+        assert!(found.len() == 5 && found.iter().all(|n| n.distance == 0f64));
+
+        // and still splits once distinct points come in, the search staying exact
+        let points = random_points(1500, 6, 5);
+        for (id, point) in points.iter().enumerate() {
+            tree.insert(1000 + id, point.clone());
+        }
+        assert!(matches!(tree.root, Node::Branch { .. }));
+        let query = random_points(1, 6, 9).remove(0);
+        let all = (0..1000).map(|_| vec![1f64; 6]).chain(points).collect::<Vec<_>>();
+        let exact = brute_force(Metric::L1, all.iter().enumerate(), &query, 10);
+        assert_eq!(recall(&tree.nearest(&query, 10), &exact), 1f64);
+    }
+
+    #[test]
+    fn test_indexed_matches_brute_force_patterns() {
+        let spec = PatternSpec { window: 6, top_k: 5, horizons: vec![2], ..Default::default() };
+        let close = random_points(300, 1, 3).into_iter().scan(100f64, |p, r| {
+            *p *= 1f64 + r[0] / 50f64;
+            Some(*p)
+        }).collect::<Vec<_>>();
+        let df = df![
+            "timestamp" => (0..300i64).collect::<Vec<_>>(),
+            "close" => close,
+        ].unwrap();
+        let spec = PatternSpec { features: vec!["close".into()], ..spec };
+        let df = mean_scaling(df.lazy(), &spec).collect().unwrap();
+        let windows = Windows::new(&df, &spec).unwrap();
+
+        let mut index = PatternIndex::new(spec.clone(), Metric::Cosine);
+        for t in 0..windows.len() {
+            let found = index.advance(&windows, t);
+            let exact = find_similar(&windows, &spec, t);
+            assert_eq!(found.len(), exact.len());
+            for (f, e) in found.iter().zip(&exact) {
+                assert!((f.similarity - e.similarity).abs() < 1e-9);
+            }
+        }
+    }
+}
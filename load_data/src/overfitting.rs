@@ -0,0 +1,163 @@
+use crate::cv::combinations;
+use crate::stats;
+
+// Corrections for picking the best of many backtests (Bailey and López de Prado): probabilistic and
+// deflated Sharpe ratios, and the probability of backtest overfitting by combinatorially symmetric
+// cross-validation. Sharpe ratios here are per bar, not annualized.
+
+const EULER_GAMMA: f64 = 0.5772156649015329;
+
+pub fn sharpe(returns: &[f64]) -> f64 {
+    stats::mean(returns) / stats::std(returns)
+}
+
+// Probability that the true Sharpe ratio is above `benchmark` given the length, skewness and
+// kurtosis of the returns, NaN for fewer than two returns.
+pub fn probabilistic_sharpe(returns: &[f64], benchmark: f64) -> f64 {
+    if returns.len() < 2 {
+        return f64::NAN;
+    }
+    let sr = sharpe(returns);
+    let (skew, kurt) = (stats::skewness(returns), stats::kurtosis(returns));
+    let denominator = (1f64 - skew * sr + (kurt - 1f64) / 4f64 * sr * sr).sqrt();
+    stats::normal_cdf((sr - benchmark) * ((returns.len() - 1) as f64).sqrt() / denominator)
+}
+
+// Sharpe ratio expected from the best of `trials` independent trials with no skill, their
+// Sharpe ratios having the variance `variance`.
+pub fn expected_max_sharpe(trials: f64, variance: f64) -> f64 {
+    if trials <= 1f64 {
+        return 0f64;
+    }
+    variance.sqrt()
+        * ((1f64 - EULER_GAMMA) * stats::normal_quantile(1f64 - 1f64 / trials)
+            + EULER_GAMMA * stats::normal_quantile(1f64 - 1f64 / (trials * std::f64::consts::E)))
+}
+
+// Correlated trials count as fewer: rho + (1 - rho) * n with rho their mean pairwise correlation.
+pub fn effective_trials(returns: &[Vec<f64>]) -> f64 {
+    let n = returns.len();
+    if n < 2 {
+        return n as f64;
+    }
+    let mut correlations = Vec::with_capacity(n * (n - 1) / 2);
+    for i in 0..n {
+        for j in i + 1..n {
+            let rho = stats::correlation(&returns[i], &returns[j]);
+            if rho.is_finite() {
+                correlations.push(rho);
+            }
+        }
+    }
+    let rho = if correlations.is_empty() { 0f64 } else { stats::mean(&correlations).clamp(0f64, 1f64) };
+    rho + (1f64 - rho) * n as f64
+}
+
+// Deflated Sharpe ratio of each trial: its probabilistic Sharpe ratio against the Sharpe ratio
+// expected from the best of the (effective) trials.
+pub fn deflated_sharpe(returns: &[Vec<f64>]) -> Vec<f64> {
+    let sharpes = returns.iter().map(|r| sharpe(r)).filter(|sr| sr.is_finite()).collect::<Vec<_>>();
+    let variance = stats::std(&sharpes).powi(2);
+    let benchmark = expected_max_sharpe(effective_trials(returns), if variance.is_finite() { variance } else { 0f64 });
+    returns.iter().map(|r| probabilistic_sharpe(r, benchmark)).collect()
+}
+
+// Probability of backtest overfitting: the bars are cut into `blocks` (even) blocks, and for every half
+// of them as in sample, how often the best trial in sample ranks below the median out of sample.
+pub fn probability_of_overfitting(returns: &[Vec<f64>], blocks: usize) -> f64 {
+    let (trials, bars) = (returns.len(), returns.first().map(|r| r.len()).unwrap_or(0));
+    let blocks = (blocks.min(bars) / 2) * 2;
+    if trials < 2 || blocks < 2 {
+        return f64::NAN;
+    }
+    // sums of the returns and their squares of each trial by block
+    let moments = returns
+        .iter()
+        .map(|r| {
+            (0..blocks)
+                .map(|b| {
+                    let block = &r[b * bars / blocks..(b + 1) * bars / blocks];
+                    let sum = block.iter().filter(|x| x.is_finite()).sum::<f64>();
+                    let squares = block.iter().filter(|x| x.is_finite()).map(|x| x * x).sum::<f64>();
+                    (sum, squares, block.len() as f64)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let sharpe_over = |trial: usize, blocks: &mut dyn Iterator<Item = usize>| {
+        let (sum, squares, n) = blocks.fold((0f64, 0f64, 0f64), |acc, b| {
+            let (s, q, n) = moments[trial][b];
+            (acc.0 + s, acc.1 + q, acc.2 + n)
+        });
+        let mean = sum / n;
+        let std = ((squares - n * mean * mean) / (n - 1f64)).sqrt();
+        if std > 0f64 {
+            mean / std
+        } else {
+            f64::NEG_INFINITY
+        }
+    };
+
+    let splits = combinations(blocks, blocks / 2);
+    let mut overfit = 0;
+    for in_sample in &splits {
+        let out_of_sample = (0..blocks).filter(|b| !in_sample.contains(b)).collect::<Vec<_>>();
+        let is = (0..trials).map(|t| sharpe_over(t, &mut in_sample.iter().copied())).collect::<Vec<_>>();
+        let oos = (0..trials).map(|t| sharpe_over(t, &mut out_of_sample.iter().copied())).collect::<Vec<_>>();
+        let best = (0..trials).max_by(|a, b| is[*a].partial_cmp(&is[*b]).unwrap()).unwrap();
+        // relative rank of the best out of sample, in (0, 1)
+        let rank = oos.iter().filter(|sr| **sr < oos[best]).count() as f64 + 1f64;
+        let omega = rank / (trials as f64 + 1f64);
+        if (omega / (1f64 - omega)).ln() <= 0f64 {
+            overfit += 1;
+        }
+    }
+    overfit as f64 / splits.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    #[test]
+    fn test_sharpe_ratios() {
+        let mut rng = Rng::new(3);
+        let skilled = (0..1000).map(|_| 0.001 + 0.01 * rng.normal()).collect::<Vec<_>>();
+        // a tenth of a standard deviation a bar over a thousand bars is significant
+        assert!(probabilistic_sharpe(&skilled, 0.0) > 0.99);
+        assert!(probabilistic_sharpe(&skilled, 0.2) < 0.01);
+
+        // with many trials the best one is expected to look good by chance
+        assert!((expected_max_sharpe(1000.0, 1.0) - 3.255).abs() < 1e-2);
+        assert_eq!(expected_max_sharpe(1.0, 1.0), 0.0);
+
+        let noise = (0..50).map(|_| (0..1000).map(|_| 0.01 * rng.normal()).collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert!((effective_trials(&noise) - 50.0).abs() < 2.0);
+        // none of them survives the deflation
+        assert!(deflated_sharpe(&noise).iter().all(|p| *p < 0.95));
+    }
+
+    #[test]
+    fn test_short_returns() {
+        // e.g. a trial over an empty frame
+        assert!(probabilistic_sharpe(&[], 0.0).is_nan());
+        assert!(probabilistic_sharpe(&[0.01], 0.0).is_nan());
+        let dsr = deflated_sharpe(&[vec![], vec![0.01, -0.005, 0.002]]);
+        assert!(dsr[0].is_nan() && dsr[1].is_finite());
+    }
+
+    #[test]
+    fn test_probability_of_overfitting() {
+        let noise = |rng: &mut Rng| (0..20).map(|_| (0..400).map(|_| 0.01 * rng.normal()).collect::<Vec<_>>()).collect::<Vec<_>>();
+        // picking the best of pure noise is overfitting half of the time on average
+        let pbo = (0..20).map(|seed| probability_of_overfitting(&noise(&mut Rng::new(seed)), 8)).collect::<Vec<_>>();
+        assert!((stats::mean(&pbo) - 0.5).abs() < 0.15);
+
+        // a trial with a real edge stays the best out of sample
+        let mut rng = Rng::new(5);
+        let mut skilled = noise(&mut rng);
+        skilled[0] = (0..400).map(|_| 0.01 + 0.01 * rng.normal()).collect();
+        assert_eq!(probability_of_overfitting(&skilled, 8), 0.0);
+    }
+}
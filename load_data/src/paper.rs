@@ -0,0 +1,586 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use binance::ws_model::{Kline, WebsocketEvent};
+use polars::prelude::*;
+
+use crate::costs::{Cost, Entry};
+use crate::data::Candles;
+use crate::engine::{Account, Bar, Engine, EngineSpec, Features, Fill, Liquidation, Liquidity, Order, OrderKind, Report, Side, Strategy};
+
+// Paper trading: closed candles from a feed step the engine one at a time, with the costs and margin
+// of its spec, and the state is saved after every candle so a restart picks up where it stopped: a
+// snapshot of the account, orders, candles and closes, and a journal the fills, ledger entries and
+// liquidations are appended to.
+// Features come from a pipeline, e.g. `aggregate`, run over the candles kept so far. The state of the
+// strategy itself is not saved, it has to be rebuilt from the account and the open orders.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    pub open_time: i64, // ms
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+impl Candle {
+    // a kline of the websocket once it is closed, None while it is still forming
+    pub fn from_kline(kline: &Kline) -> Option<Self> {
+        if !kline.is_final_bar {
+            return None;
+        }
+        Some(Self {
+            open_time: kline.start_time,
+            open: kline.open,
+            high: kline.high,
+            low: kline.low,
+            close: kline.close,
+            volume: kline.volume,
+        })
+    }
+}
+
+// Closed candles in order, None once the feed is over.
+pub trait Feed {
+    fn next(&mut self) -> Result<Option<Candle>>;
+}
+
+// Recorded candles played back, paced like a live feed or as fast as they go.
+pub struct Replay {
+    candles: Candles,
+    at: usize,
+    pub pace: Option<Duration>,
+}
+
+impl Replay {
+    // a kline frame with `openTime`, or a frame with `timestamp`
+    pub fn from_frame(df: &DataFrame) -> Result<Self> {
+        let candles = match df.column("openTime") {
+            Ok(open_time) => {
+                let mut timestamp = open_time.cast(&DataType::Int64)?;
+                timestamp.rename("timestamp");
+                let mut df = df.clone();
+                df.with_column(timestamp)?;
+                Candles::from_frame(&df)?
+            }
+            Err(_) => Candles::from_frame(df)?,
+        };
+        Ok(Self { candles, at: 0, pace: None })
+    }
+}
+
+impl Feed for Replay {
+    fn next(&mut self) -> Result<Option<Candle>> {
+        if self.at >= self.candles.len() {
+            return Ok(None);
+        }
+        if let Some(pace) = self.pace {
+            std::thread::sleep(pace);
+        }
+        let (c, i) = (&self.candles, self.at);
+        self.at += 1;
+        Ok(Some(Candle { open_time: c.timestamp[i], open: c.open[i], high: c.high[i], low: c.low[i], close: c.close[i], volume: c.volume[i] }))
+    }
+}
+
+// candles sent by another thread, e.g. by `kline_handler`
+impl Feed for Receiver<Candle> {
+    fn next(&mut self) -> Result<Option<Candle>> {
+        Ok(self.recv().ok())
+    }
+}
+
+// Handler of the websocket events of a `kline_stream`, sending the closed klines to the trader. It
+// fails once the trader is gone, which ends the event loop.
+#[allow(clippy::result_large_err)] // the error type of the websocket handler
+pub fn kline_handler(sender: Sender<Candle>) -> impl FnMut(WebsocketEvent) -> binance::errors::Result<()> + Send {
+    move |event| {
+        if let WebsocketEvent::Kline(event) = event {
+            if let Some(candle) = Candle::from_kline(&event.kline) {
+                if sender.send(candle).is_err() {
+                    return Err(binance::errors::Error::Msg("the paper trader stopped".to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Recorded websocket messages, one event per line, decoded like the websocket does and played through
+// `kline_handler` on another thread, the feed being the receiving end as for live klines.
+pub fn replay_klines(messages: &str) -> Result<Receiver<Candle>> {
+    let events = messages
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str::<WebsocketEvent>(line)
+                .map_err(|e| PolarsError::ComputeError(format!("invalid websocket message: {}: {}", e, line).into()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let (sender, receiver) = channel();
+    let mut handler = kline_handler(sender);
+    std::thread::spawn(move || {
+        for event in events {
+            if handler(event).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+#[derive(Debug, Clone)]
+pub struct PaperSpec {
+    pub engine: EngineSpec,
+    pub history: usize,         // candles kept for the pipeline, and closes for the positions and equity
+    pub state: Option<PathBuf>, // snapshot saved after every candle, next to its `.journal`
+}
+
+impl PaperSpec {
+    pub fn new(engine: EngineSpec, history: usize) -> Self {
+        Self { engine, history, state: None }
+    }
+}
+
+pub struct PaperTrader<P> {
+    pub spec: PaperSpec,
+    pipeline: P,
+    engine: Engine,
+    candles: Vec<Candle>,
+    bars: usize,           // stepped since the first start
+    journaled: [usize; 3], // fills, ledger entries and liquidations in the journal
+}
+
+fn journal_path(state: &Path) -> PathBuf {
+    state.with_extension("journal")
+}
+
+// written aside first so a crash never leaves half a file
+fn replace(path: &Path, text: &str) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, text)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+impl<P> PaperTrader<P>
+where
+    P: Fn(LazyFrame) -> LazyFrame,
+{
+    // restored from the state file when there is one
+    pub fn new(spec: PaperSpec, pipeline: P) -> Result<Self> {
+        let engine = Engine::new(&spec.engine);
+        let mut trader = Self { engine, spec, pipeline, candles: Vec::new(), bars: 0, journaled: [0; 3] };
+        if let Some(path) = trader.spec.state.clone() {
+            if path.exists() {
+                let state = std::fs::read_to_string(&path)?;
+                let journal = match std::fs::read_to_string(journal_path(&path)) {
+                    Ok(journal) => journal,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                    Err(e) => return Err(e.into()),
+                };
+                trader.restore(&state, &journal)?;
+                // without the records of a candle journaled but not in the snapshot, the feed replays it
+                replace(&journal_path(&path), &trader.journal())?;
+            }
+        }
+        Ok(trader)
+    }
+
+    pub fn account(&self) -> &Account {
+        self.engine.context().account()
+    }
+
+    pub fn equity(&self) -> f64 {
+        self.engine.context().equity()
+    }
+
+    pub fn open_orders(&self) -> impl Iterator<Item = &Order> {
+        self.engine.context().open_orders()
+    }
+
+    // the fills, ledger and liquidations since the first start, as in the journal, the positions and
+    // equity of the last `history` closes
+    pub fn report(&self) -> &Report {
+        &self.engine.report
+    }
+
+    pub fn fills(&self) -> &[Fill] {
+        &self.engine.report.fills
+    }
+
+    pub fn last_open_time(&self) -> Option<i64> {
+        self.candles.last().map(|c| c.open_time)
+    }
+
+    fn features(&self) -> Result<Features> {
+        let df = df![
+            "openTime" => self.candles.iter().map(|c| c.open_time).collect::<Vec<_>>(),
+            "open" => self.candles.iter().map(|c| c.open).collect::<Vec<_>>(),
+            "high" => self.candles.iter().map(|c| c.high).collect::<Vec<_>>(),
+            "low" => self.candles.iter().map(|c| c.low).collect::<Vec<_>>(),
+            "close" => self.candles.iter().map(|c| c.close).collect::<Vec<_>>(),
+            "volume" => self.candles.iter().map(|c| c.volume).collect::<Vec<_>>(),
+        ]?;
+        let df = (self.pipeline)(df.lazy()).collect()?;
+        Features::from_frame(&df.tail(Some(1)))
+    }
+
+    // Steps the engine on a closed candle. Candles not after the last one, as a feed replays them on a
+    // restart, are skipped.
+    pub fn on_candle<S: Strategy>(&mut self, candle: Candle, strategy: &mut S) -> Result<()> {
+        let previous = self.last_open_time();
+        if previous.map(|t| candle.open_time <= t).unwrap_or(false) {
+            return Ok(());
+        }
+        self.candles.push(candle);
+        let excess = self.candles.len().saturating_sub(self.spec.history.max(1));
+        self.candles.drain(..excess);
+
+        let features = self.features()?;
+        let bar = Bar {
+            index: self.bars,
+            timestamp: candle.open_time,
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: candle.close,
+            volume: candle.volume,
+            features: &features,
+            row: 0,
+        };
+        let fundings = match (self.spec.engine.costs.as_ref().and_then(|c| c.funding.as_ref()), previous) {
+            (Some(funding), Some(previous)) => funding.between(previous, candle.open_time).collect(),
+            _ => Vec::new(),
+        };
+        self.engine.step(&bar, &fundings, strategy);
+        self.bars += 1;
+        let report = &mut self.engine.report;
+        for closes in [&mut report.positions, &mut report.equity] {
+            let excess = closes.len().saturating_sub(self.spec.history.max(1));
+            closes.drain(..excess);
+        }
+        self.save()
+    }
+
+    // until the feed is over
+    pub fn run<F: Feed, S: Strategy>(&mut self, feed: &mut F, strategy: &mut S) -> Result<()> {
+        while let Some(candle) = feed.next()? {
+            self.on_candle(candle, strategy)?;
+        }
+        Ok(())
+    }
+
+    // Appends the new records to the journal, then replaces the snapshot counting them.
+    pub fn save(&mut self) -> Result<()> {
+        let path = match &self.spec.state {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        let records = self.records(self.journaled);
+        if !records.is_empty() {
+            let mut journal = std::fs::OpenOptions::new().create(true).append(true).open(journal_path(&path))?;
+            journal.write_all(records.as_bytes())?;
+        }
+        replace(&path, &self.state())?;
+        self.journaled = self.counts();
+        Ok(())
+    }
+
+    fn counts(&self) -> [usize; 3] {
+        let report = &self.engine.report;
+        [report.fills.len(), report.ledger.len(), report.liquidations.len()]
+    }
+
+    // The snapshot, one record per line, a tag and space separated values.
+    pub fn state(&self) -> String {
+        let ctx = self.engine.context();
+        let a = ctx.account();
+        let mut lines = vec![
+            format!("bars {}", self.bars),
+            format!("next_id {}", ctx.next_id),
+            format!("mark {}", ctx.mark),
+            format!("account {} {} {} {} {}", a.capital, a.position, a.entry_price, a.realized, a.costs),
+            format!("journal {} {} {}", self.counts()[0], self.counts()[1], self.counts()[2]),
+        ];
+        for o in ctx.open_orders() {
+            let (kind, first, second) = match o.kind {
+                OrderKind::Market => ("Market", f64::NAN, f64::NAN),
+                OrderKind::Limit(price) => ("Limit", price, f64::NAN),
+                OrderKind::Stop(stop) => ("Stop", stop, f64::NAN),
+                OrderKind::StopLimit { stop, limit } => ("StopLimit", stop, limit),
+            };
+            lines.push(format!(
+                "order {} {} {} {} {} {} {} {} {} {}",
+                o.id, o.side.name(), o.quantity, kind, first, second, o.reduce_only, o.post_only, o.arrived, o.triggered
+            ));
+        }
+        for c in &self.candles {
+            lines.push(format!("candle {} {} {} {} {} {}", c.open_time, c.open, c.high, c.low, c.close, c.volume));
+        }
+        let report = &self.engine.report;
+        for (position, equity) in report.positions.iter().zip(&report.equity) {
+            lines.push(format!("close {} {}", position, equity));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    // The journal, every fill, ledger entry and liquidation in the same format.
+    pub fn journal(&self) -> String {
+        self.records([0; 3])
+    }
+
+    // from the counts of fills, ledger entries and liquidations on
+    fn records(&self, from: [usize; 3]) -> String {
+        let report = &self.engine.report;
+        let fills = report.fills[from[0]..].iter().map(|f| {
+            format!(
+                "fill {} {} {} {} {} {} {}\n",
+                f.order, f.bar, f.timestamp, f.side.name(), f.quantity, f.price, f.liquidity.name()
+            )
+        });
+        let entries = report.ledger[from[1]..].iter().map(|e| format!("entry {} {} {}\n", e.timestamp, e.cost.name(), e.amount));
+        let liquidations = report.liquidations[from[2]..]
+            .iter()
+            .map(|l| format!("liquidation {} {} {} {}\n", l.bar, l.timestamp, l.position, l.price));
+        fills.chain(entries).chain(liquidations).collect()
+    }
+
+    // The journal may hold records past the counts of the snapshot, of a candle stepped but not saved.
+    fn restore(&mut self, state: &str, journal: &str) -> Result<()> {
+        let invalid = |line: &str| PolarsError::ComputeError(format!("invalid paper state: {}", line).into());
+        let mut engine = Engine::new(&self.spec.engine);
+        let mut candles = Vec::new();
+        let (mut journaled, mut restored) = ([0usize; 3], [0usize; 3]);
+        for line in state.lines().chain(journal.lines()).filter(|l| !l.trim().is_empty()) {
+            let fields = line.split(' ').collect::<Vec<_>>();
+            let number = |i: usize| fields.get(i).and_then(|f| f.parse::<f64>().ok()).ok_or_else(|| invalid(line));
+            let integer = |i: usize| fields.get(i).and_then(|f| f.parse::<i64>().ok()).ok_or_else(|| invalid(line));
+            let flag = |i: usize| fields.get(i).and_then(|f| f.parse::<bool>().ok()).ok_or_else(|| invalid(line));
+            let side = |i: usize| match fields.get(i) {
+                Some(&"Buy") => Ok(Side::Buy),
+                Some(&"Sell") => Ok(Side::Sell),
+                _ => Err(invalid(line)),
+            };
+            let record = match fields[0] {
+                "fill" => Some(0),
+                "entry" => Some(1),
+                "liquidation" => Some(2),
+                _ => None,
+            };
+            if let Some(record) = record {
+                if restored[record] == journaled[record] {
+                    continue;
+                }
+                restored[record] += 1;
+            }
+            match fields[0] {
+                "bars" => self.bars = integer(1)? as usize,
+                "journal" => journaled = [integer(1)? as usize, integer(2)? as usize, integer(3)? as usize],
+                "next_id" => engine.ctx.next_id = integer(1)? as u64,
+                "mark" => engine.ctx.mark = number(1)?,
+                "account" => {
+                    engine.ctx.account = Account {
+                        capital: number(1)?,
+                        position: number(2)?,
+                        entry_price: number(3)?,
+                        realized: number(4)?,
+                        costs: number(5)?,
+                    }
+                }
+                "order" => {
+                    let kind = match fields.get(4) {
+                        Some(&"Market") => OrderKind::Market,
+                        Some(&"Limit") => OrderKind::Limit(number(5)?),
+                        Some(&"Stop") => OrderKind::Stop(number(5)?),
+                        Some(&"StopLimit") => OrderKind::StopLimit { stop: number(5)?, limit: number(6)? },
+                        _ => return Err(invalid(line)),
+                    };
+                    let mut order = Order::new(side(2)?, number(3)?, kind);
+                    order.id = integer(1)? as u64;
+                    order.reduce_only = flag(7)?;
+                    order.post_only = flag(8)?;
+                    order.arrived = flag(9)?;
+                    order.triggered = flag(10)?;
+                    engine.ctx.open_orders.push(order);
+                }
+                "candle" => candles.push(Candle {
+                    open_time: integer(1)?,
+                    open: number(2)?,
+                    high: number(3)?,
+                    low: number(4)?,
+                    close: number(5)?,
+                    volume: number(6)?,
+                }),
+                "fill" => engine.report.fills.push(Fill {
+                    order: integer(1)? as u64,
+                    bar: integer(2)? as usize,
+                    timestamp: integer(3)?,
+                    side: side(4)?,
+                    quantity: number(5)?,
+                    price: number(6)?,
+                    liquidity: match fields.get(7) {
+                        Some(&"Maker") => Liquidity::Maker,
+                        Some(&"Taker") => Liquidity::Taker,
+                        _ => return Err(invalid(line)),
+                    },
+                }),
+                "entry" => {
+                    let cost = match fields.get(2) {
+                        Some(&"Fee") => Cost::Fee,
+                        Some(&"Slippage") => Cost::Slippage,
+                        Some(&"Funding") => Cost::Funding,
+                        Some(&"Liquidation") => Cost::Liquidation,
+                        _ => return Err(invalid(line)),
+                    };
+                    engine.report.ledger.push(Entry::new(integer(1)?, cost, number(3)?));
+                }
+                "liquidation" => engine.report.liquidations.push(Liquidation {
+                    bar: integer(1)? as usize,
+                    timestamp: integer(2)?,
+                    position: number(3)?,
+                    price: number(4)?,
+                }),
+                "close" => {
+                    engine.report.positions.push(number(1)?);
+                    engine.report.equity.push(number(2)?);
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+        if restored != journaled {
+            return Err(PolarsError::ComputeError(format!("paper journal with {:?} records short of {:?}", restored, journaled).into()));
+        }
+        self.engine = engine;
+        self.candles = candles;
+        self.journaled = journaled;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::costs::{CostModel, Fees, Slippage};
+    use crate::data::aggregate;
+    use crate::engine::{run, Context};
+    use crate::synthetic::{generate, Model, Regime, SyntheticSpec};
+
+    // buys at an abnormal volume and sells half a percent away from the entry, from the account alone
+    struct Breakout;
+
+    impl Strategy for Breakout {
+        fn on_bar(&mut self, bar: &Bar, ctx: &mut Context) {
+            if ctx.open_orders().count() > 0 {
+                return;
+            }
+            let position = ctx.position();
+            if position != 0f64 && (bar.close / ctx.account().entry_price - 1f64).abs() >= 0.005 {
+                ctx.submit(Order::market(Side::Sell, position));
+            } else if position == 0f64 && bar.feature("abnormal volume") == Some(1f64) {
+                ctx.submit(Order::market(Side::Buy, 0.1));
+            }
+        }
+    }
+
+    fn candles() -> DataFrame {
+        let spec = SyntheticSpec::new(Model::Gbm(Regime { drift: 0.0, volatility: 0.003 }), 300, 7);
+        generate(&spec).unwrap().select(["openTime", "open", "high", "low", "close", "volume"]).unwrap()
+    }
+
+    #[test]
+    fn test_replay_matches_backtest() {
+        let df = candles();
+        let pipeline = |lf| aggregate(lf, 1.5, 0.01, 20);
+        let mut trader = PaperTrader::new(PaperSpec::new(EngineSpec::default(), 50), pipeline).unwrap();
+        trader.run(&mut Replay::from_frame(&df).unwrap(), &mut Breakout).unwrap();
+
+        // the same fills as the backtest over the whole frame, the features being causal
+        let report = run(&aggregate(df.lazy(), 1.5, 0.01, 20).collect().unwrap(), &mut Breakout, &EngineSpec::default()).unwrap();
+        assert!(!report.fills.is_empty());
+        assert_eq!(trader.fills(), report.fills.as_slice());
+        assert!((trader.equity() - report.equity.last().unwrap()).abs() < 1e-9);
+    }
+
+    // the websocket messages of the candles, each closed kline after an update while it was forming
+    fn kline_messages(df: &DataFrame) -> String {
+        let c = Candles::from_frame(&df.clone().lazy().with_column(col("openTime").alias("timestamp")).collect().unwrap()).unwrap();
+        let kline = |i: usize, close: f64, closed: bool| {
+            format!(
+                r#"{{"e":"kline","E":{},"s":"BTCUSDT","k":{{"t":{},"T":{},"s":"BTCUSDT","i":"15m","f":0,"L":0,"o":"{}","c":"{}","h":"{}","l":"{}","v":"{}","n":0,"x":{},"q":"0","V":"0","Q":"0","B":"0"}}}}"#,
+                c.timestamp[i] + 1, c.timestamp[i], c.timestamp[i] + 899_999, c.open[i], close, c.high[i], c.low[i], c.volume[i], closed
+            )
+        };
+        (0..c.len())
+            .flat_map(|i| [kline(i, c.open[i] * 1.01, false), kline(i, c.close[i], true)])
+            .chain([r#"{"e":"trade","E":0,"s":"BTCUSDT","t":1,"p":"1","q":"1","b":1,"a":2,"T":0,"m":true,"M":true}"#.to_string()])
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_kline_replay_matches_backtest() {
+        let df = candles();
+        let pipeline = |lf| aggregate(lf, 1.5, 0.01, 20);
+        let mut trader = PaperTrader::new(PaperSpec::new(EngineSpec::default(), 50), pipeline).unwrap();
+        trader.run(&mut replay_klines(&kline_messages(&df)).unwrap(), &mut Breakout).unwrap();
+
+        let report = run(&aggregate(df.lazy(), 1.5, 0.01, 20).collect().unwrap(), &mut Breakout, &EngineSpec::default()).unwrap();
+        assert_eq!(trader.bars, 300);
+        assert!(!report.fills.is_empty());
+        assert_eq!(trader.fills(), report.fills.as_slice());
+        assert!((trader.equity() - report.equity.last().unwrap()).abs() < 1e-9);
+        assert!(replay_klines("{\"e\":\"kline\"}").is_err());
+    }
+
+    #[test]
+    fn test_restart() {
+        let df = candles();
+        let path = std::env::temp_dir().join(format!("paper-state-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(journal_path(&path));
+        let engine = EngineSpec { costs: Some(CostModel::new(Fees::vip(0), Slippage::Fixed(1.0))), ..Default::default() };
+        let spec = PaperSpec { state: Some(path.clone()), ..PaperSpec::new(engine.clone(), 50) };
+        let pipeline = |lf| aggregate(lf, 1.5, 0.01, 20);
+
+        let mut whole = PaperTrader::new(PaperSpec::new(engine, 50), pipeline).unwrap();
+        whole.run(&mut Replay::from_frame(&df).unwrap(), &mut Breakout).unwrap();
+
+        // stopped after 150 candles, then restarted on a feed replaying from the start
+        let mut first = PaperTrader::new(spec.clone(), pipeline).unwrap();
+        first.run(&mut Replay::from_frame(&df.slice(0, 150)).unwrap(), &mut Breakout).unwrap();
+        let journaled = first.journal();
+        assert!(!first.state().contains("fill"));
+        drop(first);
+        // as if it crashed after journaling the next candle, before its snapshot
+        let journal = journal_path(&path);
+        let mut ahead = std::fs::OpenOptions::new().append(true).open(&journal).unwrap();
+        ahead.write_all(b"fill 99 150 0 Buy 1 1 Taker\nentry 0 Fee -1\n").unwrap();
+        drop(ahead);
+        let mut second = PaperTrader::new(spec.clone(), pipeline).unwrap();
+        assert_eq!(second.last_open_time(), Some(149 * 900_000 + 1_648_771_200_000));
+        assert_eq!(std::fs::read_to_string(&journal).unwrap(), journaled);
+        second.run(&mut Replay::from_frame(&df).unwrap(), &mut Breakout).unwrap();
+        // the records in the journal are those of the uninterrupted run
+        let third = PaperTrader::new(spec, pipeline).unwrap();
+        assert_eq!(third.journal(), whole.journal());
+        assert_eq!(third.state(), whole.state());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&journal).unwrap();
+
+        assert_eq!(second.fills(), whole.fills());
+        assert!(!whole.report().ledger.is_empty());
+        assert_eq!(second.report().ledger, whole.report().ledger);
+        assert_eq!(second.report().liquidations, whole.report().liquidations);
+        // the closes of the last `history` bars, the last one at the current equity
+        assert_eq!(whole.report().equity.len(), 50);
+        assert_eq!(second.report().positions, whole.report().positions);
+        assert_eq!(second.report().equity, whole.report().equity);
+        assert_eq!(*whole.report().equity.last().unwrap(), whole.equity());
+        assert_eq!(second.state(), whole.state());
+    }
+}
@@ -0,0 +1,340 @@
+use polars::prelude::*;
+
+// Hypo: similar price actions go on the same pattern with a high probability.
+// See `hypothesis/hypo.canvas` for the mean scaling and the weighted cosine similarity.
+
+#[derive(Debug, Clone)]
+pub struct PatternSpec {
+    pub window: usize,         // N, the number of ticks in a window
+    pub alpha: f64,            // decay of weights, w = alpha^(t-k)
+    pub top_k: usize,          // K, the number of matches per timestamp
+    pub features: Vec<String>, // raw columns to be scaled
+    pub horizons: Vec<usize>,  // ticks after a match to report returns for
+}
+
+impl Default for PatternSpec {
+    fn default() -> Self {
+        Self {
+            window: 20,
+            alpha: 0.9,
+            top_k: 10,
+            features: ["open", "high", "low", "close", "volume"].iter().map(|f| f.to_string()).collect(),
+            horizons: vec![1, 4, 16],
+        }
+    }
+}
+
+impl PatternSpec {
+    pub fn scaled_features(&self) -> Vec<String> {
+        self.features.iter().map(|f| scaled_name(f)).collect()
+    }
+
+    // the earliest a window may end before the query so that it neither overlaps
+    // the query window nor has outcomes unknown at the query time
+    pub fn exclusion(&self) -> usize {
+        self.window.max(self.horizons.iter().copied().max().unwrap_or(0))
+    }
+
+    // element-wise weights for a flattened window, oldest tick first
+    pub fn weights(&self) -> Vec<f64> {
+        (0..self.window)
+            .flat_map(|j| {
+                let w = self.alpha.powi((self.window - 1 - j) as i32);
+                std::iter::repeat_n(w, self.features.len())
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub query: usize,
+    pub index: usize,
+    pub similarity: f64,
+}
+
+pub fn scaled_name(feature: &str) -> String {
+    format!("scaled {}", feature)
+}
+
+pub fn forward_return_name(horizon: usize) -> String {
+    format!("forward return {}", horizon)
+}
+
+pub fn mean_scaling(lf: LazyFrame, spec: &PatternSpec) -> LazyFrame {
+    let rolling_option = RollingOptions {
+        window_size: Duration::new(spec.window as i64),
+        min_periods: spec.window,
+        weights: None,
+        center: false,
+        by: None,
+        closed_window: None,
+    };
+
+    lf.with_columns(
+        spec.features
+            .iter()
+            .map(|f| scaled(f, &rolling_option))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn scaled(feature: &str, rolling_option: &RollingOptions) -> Expr {
+    let p = col(feature).cast(DataType::Float64);
+    ((p.clone() - p.clone().rolling_mean(rolling_option.clone())) / p.rolling_std(rolling_option.clone()))
+        .alias(&scaled_name(feature))
+}
+
+pub fn weighted_cosine_similarity(a: &[f64], b: &[f64], weights: &[f64]) -> f64 {
+    let (mut ab, mut aa, mut bb) = (0f64, 0f64, 0f64);
+    for ((x, y), w) in a.iter().zip(b).zip(weights) {
+        ab += w * x * y;
+        aa += w * x * x;
+        bb += w * y * y;
+    }
+    if aa == 0f64 || bb == 0f64 {
+        return 0f64;
+    }
+    ab / (aa.sqrt() * bb.sqrt())
+}
+
+// Scaled features of a frame already passed through `mean_scaling`, flattened per window.
+pub struct Windows {
+    values: Vec<Vec<f64>>, // [feature][row], NaN where unknown
+    window: usize,
+}
+
+impl Windows {
+    pub fn new(df: &DataFrame, spec: &PatternSpec) -> Result<Self> {
+        let values = spec
+            .scaled_features()
+            .iter()
+            .map(|name| {
+                let s = df.column(name)?.cast(&DataType::Float64)?;
+                Ok(s.f64()?.into_iter().map(|v| v.unwrap_or(f64::NAN)).collect())
+            })
+            .collect::<Result<Vec<Vec<f64>>>>()?;
+        Ok(Self { values, window: spec.window })
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.first().map(|v| v.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the window ending at `end`, oldest tick first, or None when any value is missing
+    pub fn get(&self, end: usize) -> Option<Vec<f64>> {
+        if end + 1 < self.window || end >= self.len() {
+            return None;
+        }
+        let start = end + 1 - self.window;
+        let flat = (start..=end)
+            .flat_map(|row| self.values.iter().map(move |feature| feature[row]))
+            .collect::<Vec<_>>();
+        if flat.iter().any(|v| !v.is_finite()) {
+            return None;
+        }
+        Some(flat)
+    }
+}
+
+// How windows are compared in the search, see `dtw::Dtw` for an alternative to the canvas.
+pub trait Matcher {
+    type Query;
+
+    fn prepare(&self, window: Vec<f64>) -> Self::Query;
+
+    // similarity to a prepared query where higher is more similar,
+    // or None when it cannot beat `threshold`, the K-th best similarity so far
+    fn similarity(&self, query: &Self::Query, candidate: &[f64], threshold: f64) -> Option<f64>;
+}
+
+pub struct WeightedCosine {
+    pub weights: Vec<f64>,
+}
+
+impl WeightedCosine {
+    pub fn new(spec: &PatternSpec) -> Self {
+        Self { weights: spec.weights() }
+    }
+}
+
+impl Matcher for WeightedCosine {
+    type Query = Vec<f64>;
+
+    fn prepare(&self, window: Vec<f64>) -> Self::Query {
+        window
+    }
+
+    fn similarity(&self, query: &Self::Query, candidate: &[f64], _threshold: f64) -> Option<f64> {
+        Some(weighted_cosine_similarity(query, candidate, &self.weights))
+    }
+}
+
+// Brute force search of the most similar windows ending before `query - exclusion`.
+pub fn find_similar(windows: &Windows, spec: &PatternSpec, query: usize) -> Vec<Match> {
+    find_similar_with(&WeightedCosine::new(spec), windows, spec, query)
+}
+
+pub fn find_similar_with<M: Matcher>(matcher: &M, windows: &Windows, spec: &PatternSpec, query: usize) -> Vec<Match> {
+    let target = match windows.get(query) {
+        Some(target) => matcher.prepare(target),
+        None => return Vec::new(),
+    };
+    let last = match query.checked_sub(spec.exclusion()) {
+        Some(last) => last,
+        None => return Vec::new(),
+    };
+
+    let mut matches: Vec<Match> = Vec::with_capacity(spec.top_k + 1);
+    for index in 0..=last {
+        let candidate = match windows.get(index) {
+            Some(candidate) => candidate,
+            None => continue,
+        };
+        let threshold = if matches.len() < spec.top_k {
+            f64::NEG_INFINITY
+        } else {
+            matches.last().map(|m| m.similarity).unwrap_or(f64::NEG_INFINITY)
+        };
+        if let Some(similarity) = matcher.similarity(&target, &candidate, threshold) {
+            if matches.len() < spec.top_k || similarity > threshold {
+                let at = matches.partition_point(|m| m.similarity >= similarity);
+                matches.insert(at, Match { query, index, similarity });
+                matches.truncate(spec.top_k);
+            }
+        }
+    }
+    matches
+}
+
+// Top-K similar windows for every row of `df` (or only `queries`), with what happened after each match.
+pub fn similar_patterns(df: &DataFrame, spec: &PatternSpec, queries: Option<&[usize]>) -> Result<DataFrame> {
+    similar_patterns_with(&WeightedCosine::new(spec), df, spec, queries)
+}
+
+pub fn similar_patterns_with<M: Matcher>(
+    matcher: &M,
+    df: &DataFrame,
+    spec: &PatternSpec,
+    queries: Option<&[usize]>,
+) -> Result<DataFrame> {
+    let windows = Windows::new(df, spec)?;
+    let queries = match queries {
+        Some(queries) => queries.to_vec(),
+        None => (0..df.height()).collect(),
+    };
+    let matches = queries
+        .into_iter()
+        .flat_map(|query| find_similar_with(matcher, &windows, spec, query))
+        .collect::<Vec<_>>();
+    matches_to_frame(df, spec, &matches)
+}
+
+pub fn matches_to_frame(df: &DataFrame, spec: &PatternSpec, matches: &[Match]) -> Result<DataFrame> {
+    let queries = IdxCa::from_vec("query", matches.iter().map(|m| m.query as IdxSize).collect());
+    let indices = IdxCa::from_vec("index", matches.iter().map(|m| m.index as IdxSize).collect());
+
+    let mut ranks = Vec::with_capacity(matches.len());
+    let mut rank = 0u32;
+    for (i, m) in matches.iter().enumerate() {
+        rank = if i > 0 && matches[i - 1].query == m.query { rank + 1 } else { 1 };
+        ranks.push(rank);
+    }
+
+    let timestamp = df.column("timestamp")?;
+    let mut columns = vec![
+        timestamp.take(&queries)?,
+        Series::new("rank", ranks),
+        timestamp.take(&indices)?.rename("match timestamp").clone(),
+        Series::new("similarity", matches.iter().map(|m| m.similarity).collect::<Vec<_>>()),
+    ];
+
+    let close = df.column("close")?.cast(&DataType::Float64)?;
+    let close = close.f64()?;
+    for &horizon in &spec.horizons {
+        let returns = matches
+            .iter()
+            .map(|m| match (close.get(m.index), close.get(m.index + horizon)) {
+                (Some(base), Some(after)) => Some(after / base - 1f64),
+                _ => None,
+            })
+            .collect::<Float64Chunked>();
+        columns.push(returns.into_series().rename(&forward_return_name(horizon)).clone());
+    }
+
+    if let Ok(trend) = df.column("trend from base") {
+        columns.push(trend.take(&indices)?);
+    }
+
+    DataFrame::new(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn periodic(height: usize, period: usize) -> DataFrame {
+        let phase = (0..height).map(|i| (i % period) as f64 / period as f64 * std::f64::consts::TAU).collect::<Vec<_>>();
+        let open = phase.iter().map(|p| 100f64 + p.sin()).collect::<Vec<_>>();
+        let close = phase.iter().map(|p| 100f64 + (p + 0.3).sin()).collect::<Vec<_>>();
+        let high = open.iter().zip(&close).map(|(o, c)| o.max(*c) + 0.2).collect::<Vec<_>>();
+        let low = open.iter().zip(&close).map(|(o, c)| o.min(*c) - 0.2).collect::<Vec<_>>();
+        let volume = phase.iter().map(|p| 10f64 + p.cos()).collect::<Vec<_>>();
+        df![
+            "timestamp" => (0..height as i64).map(|i| i * 60_000).collect::<Vec<_>>(),
+            "open" => open,
+            "high" => high,
+            "low" => low,
+            "close" => close,
+            "volume" => volume,
+        ]
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mean_scaling() {
+        let spec = PatternSpec { window: 3, features: vec!["close".into()], ..Default::default() };
+        let df = df!["close" => [1f64, 2.0, 3.0, 5.0]].unwrap();
+        let scaled = mean_scaling(df.lazy(), &spec).collect().unwrap();
+        let scaled = scaled.column("scaled close").unwrap().f64().unwrap();
+
+        assert_eq!(scaled.get(1), None);
+        assert!((scaled.get(2).unwrap() - 1f64).abs() < 1e-9);
+        // mean of [2, 3, 5] is 10/3 and the sample std is sqrt(7/3)
+        let expected = (5f64 - 10f64 / 3f64) / (7f64 / 3f64).sqrt();
+        assert!((scaled.get(3).unwrap() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_weighted_cosine_similarity() {
+        let weights = [1f64, 0.5, 0.25];
+        assert!((weighted_cosine_similarity(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0], &weights) - 1f64).abs() < 1e-12);
+        assert!((weighted_cosine_similarity(&[1.0, 2.0, 3.0], &[-1.0, -2.0, -3.0], &weights) + 1f64).abs() < 1e-12);
+        assert_eq!(weighted_cosine_similarity(&[0.0, 0.0, 0.0], &[1.0, 2.0, 3.0], &weights), 0f64);
+    }
+
+    #[test]
+    fn test_similar_patterns() {
+        let period = 24;
+        let spec = PatternSpec { window: 8, top_k: 3, horizons: vec![1, 4], ..Default::default() };
+        let df = mean_scaling(periodic(200, period).lazy(), &spec).collect().unwrap();
+        let query = 150;
+        let matches = similar_patterns(&df, &spec, Some(&[query])).unwrap();
+
+        assert_eq!(matches.height(), 3);
+        let similarity = matches.column("similarity").unwrap().f64().unwrap();
+        assert!(similarity.get(0).unwrap() > 0.999);
+        let offsets = matches
+            .column("match timestamp").unwrap()
+            .i64().unwrap()
+            .into_no_null_iter()
+            .map(|t| query as i64 - t / 60_000)
+            .collect::<Vec<_>>();
+        assert!(offsets.iter().all(|o| o % period as i64 == 0 && *o >= spec.exclusion() as i64));
+        assert!(matches.column(&forward_return_name(4)).is_ok());
+    }
+}
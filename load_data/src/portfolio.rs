@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+
+use polars::prelude::*;
+
+use crate::data::{f64_column, timestamp_series, Interval};
+use crate::metrics::{equity, metrics, Metrics};
+use crate::stats;
+
+// Portfolio backtest of many symbols sharing one capital. At the close of each bar the signals (-1..1)
+// are turned into weights on the equity by the allocation, held over the next bar close to close.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asset {
+    pub symbol: String,
+    pub timestamp: Vec<i64>,
+    pub close: Vec<f64>,
+    pub signal: Vec<f64>,
+}
+
+impl Asset {
+    // `timestamp` and `close` of a frame with its `signal` column, e.g. the position of `with_position`
+    pub fn from_frame(symbol: &str, df: &DataFrame, signal: &str) -> Result<Self> {
+        let timestamp = df.column("timestamp")?.cast(&DataType::Int64)?;
+        Ok(Self {
+            symbol: symbol.to_string(),
+            timestamp: timestamp.i64()?.into_no_null_iter().collect(),
+            close: f64_column(df, "close")?,
+            signal: f64_column(df, signal)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Allocation {
+    EqualWeight,
+    InverseVolatility,
+    RiskParity, // equal contributions to the variance of the book
+    SignalProportional,
+}
+
+impl Allocation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Allocation::EqualWeight => "EqualWeight",
+            Allocation::InverseVolatility => "InverseVolatility",
+            Allocation::RiskParity => "RiskParity",
+            Allocation::SignalProportional => "SignalProportional",
+        }
+    }
+
+    fn needs_covariance(&self) -> bool {
+        matches!(self, Allocation::InverseVolatility | Allocation::RiskParity)
+    }
+}
+
+// Symbols whose positioned returns are correlated at `threshold` or more count as one bet, their
+// weights together capped at `cap`, a single symbol too. A long and a short of correlated symbols
+// hedge each other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CorrelationCap {
+    pub threshold: f64,
+    pub cap: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortfolioSpec {
+    pub allocation: Allocation,
+    pub gross: f64, // sum of the absolute weights when fully invested
+    pub max_positions: Option<usize>,
+    pub correlation_cap: Option<CorrelationCap>,
+    pub window: usize, // bars of returns estimating the volatilities and correlations
+    pub fee: f64,      // paid on the turnover, e.g. `Fees::vip(0).rate(Liquidity::Taker)`
+    pub interval: Interval, // of the bars, annualizes the metrics of the portfolio
+}
+
+impl PortfolioSpec {
+    pub fn new(allocation: Allocation, interval: Interval) -> Self {
+        Self { allocation, gross: 1f64, max_positions: None, correlation_cap: None, window: 100, fee: 0f64, interval }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Portfolio {
+    pub symbols: Vec<String>,
+    pub timestamp: Vec<i64>,
+    pub weights: Vec<Vec<f64>>,      // held over each bar, by symbol
+    pub contribution: Vec<Vec<f64>>, // to the return of the book, by symbol
+    pub turnover: Vec<Vec<f64>>,     // by symbol
+    pub returns: Vec<f64>,           // of the book after fees
+    pub interval: Interval,
+}
+
+// the timestamps every asset has, with the close and signal of each asset at them
+fn align(assets: &[Asset]) -> (Vec<i64>, Vec<Vec<f64>>, Vec<Vec<f64>>) {
+    let mut counts = BTreeMap::new();
+    for asset in assets {
+        for t in &asset.timestamp {
+            *counts.entry(*t).or_insert(0) += 1;
+        }
+    }
+    let timestamp = counts.into_iter().filter(|(_, c)| *c == assets.len()).map(|(t, _)| t).collect::<Vec<_>>();
+    let (mut close, mut signal) = (Vec::new(), Vec::new());
+    for asset in assets {
+        let at = asset.timestamp.iter().enumerate().map(|(i, t)| (*t, i)).collect::<BTreeMap<_, _>>();
+        close.push(timestamp.iter().map(|t| asset.close[at[t]]).collect());
+        signal.push(timestamp.iter().map(|t| asset.signal[at[t]]).collect());
+    }
+    (timestamp, close, signal)
+}
+
+fn covariance(returns: &[&[f64]]) -> Vec<Vec<f64>> {
+    let means = returns.iter().map(|r| stats::mean(r)).collect::<Vec<_>>();
+    let n = returns.first().map(|r| r.len()).unwrap_or(0) as f64;
+    (0..returns.len())
+        .map(|i| {
+            (0..returns.len())
+                .map(|j| returns[i].iter().zip(returns[j]).map(|(a, b)| (a - means[i]) * (b - means[j])).sum::<f64>() / (n - 1f64))
+                .collect()
+        })
+        .collect()
+}
+
+// Weights with equal risk contributions w_i (C w)_i, by multiplicative updates from inverse volatility.
+pub fn risk_parity(covariance: &[Vec<f64>]) -> Vec<f64> {
+    let n = covariance.len();
+    let mut weights = (0..n).map(|i| 1f64 / covariance[i][i].sqrt()).collect::<Vec<_>>();
+    for _ in 0..200 {
+        let contributions = (0..n)
+            .map(|i| weights[i] * (0..n).map(|j| covariance[i][j] * weights[j]).sum::<f64>())
+            .collect::<Vec<_>>();
+        let target = stats::mean(&contributions);
+        if contributions.iter().any(|c| *c <= 0f64) {
+            break;
+        }
+        weights.iter_mut().zip(&contributions).for_each(|(w, c)| *w *= (target / c).sqrt());
+        let total = weights.iter().sum::<f64>();
+        weights.iter_mut().for_each(|w| *w /= total);
+    }
+    weights
+}
+
+// Weights decided at the close of a bar from the signals and the trailing returns of the assets.
+fn allocate(signals: &[f64], returns: &[&[f64]], spec: &PortfolioSpec) -> Vec<f64> {
+    let n = signals.len();
+    let signals = signals.iter().map(|s| if s.is_finite() { s.clamp(-1f64, 1f64) } else { 0f64 }).collect::<Vec<_>>();
+    let mut held = (0..n).filter(|i| signals[*i] != 0f64).collect::<Vec<_>>();
+    // the strongest signals first, the earlier symbols on ties
+    held.sort_by(|a, b| signals[*b].abs().partial_cmp(&signals[*a].abs()).unwrap().then(a.cmp(b)));
+    held.truncate(spec.max_positions.unwrap_or(n));
+    let mut weights = vec![0f64; n];
+    if held.is_empty() {
+        return weights;
+    }
+
+    let estimated = returns.first().map(|r| r.len() >= spec.window.max(2)).unwrap_or(false);
+    if spec.allocation.needs_covariance() && !estimated {
+        return weights;
+    }
+    // returns of the positions, a short gaining when the asset falls
+    let positioned = held
+        .iter()
+        .map(|i| returns[*i].iter().map(|r| r * signals[*i].signum()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let covariance = if estimated { covariance(&positioned.iter().map(|r| r.as_slice()).collect::<Vec<_>>()) } else { Vec::new() };
+
+    let raw = match spec.allocation {
+        Allocation::EqualWeight => vec![1f64; held.len()],
+        Allocation::InverseVolatility => (0..held.len()).map(|k| 1f64 / covariance[k][k].sqrt()).collect(),
+        Allocation::RiskParity => risk_parity(&covariance),
+        Allocation::SignalProportional => held.iter().map(|i| signals[*i].abs()).collect(),
+    };
+    let total = raw.iter().filter(|w| w.is_finite()).sum::<f64>();
+    let mut sized = raw.iter().map(|w| if w.is_finite() && total > 0f64 { w / total * spec.gross } else { 0f64 }).collect::<Vec<_>>();
+
+    if let (Some(cap), true) = (spec.correlation_cap, estimated) {
+        let correlation = |a: usize, b: usize| covariance[a][b] / (covariance[a][a] * covariance[b][b]).sqrt();
+        // the clusters around the largest weights first
+        let mut order = (0..held.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| sized[*b].partial_cmp(&sized[*a]).unwrap());
+        for k in order {
+            let cluster = (0..held.len()).filter(|j| *j == k || correlation(k, *j) >= cap.threshold).collect::<Vec<_>>();
+            let exposure = cluster.iter().map(|j| sized[*j]).sum::<f64>();
+            if exposure > cap.cap {
+                cluster.iter().for_each(|j| sized[*j] *= cap.cap / exposure);
+            }
+        }
+    }
+
+    for (k, i) in held.iter().enumerate() {
+        weights[*i] = sized[k] * signals[*i].signum();
+    }
+    weights
+}
+
+pub fn backtest_portfolio(assets: &[Asset], spec: &PortfolioSpec) -> Portfolio {
+    let (timestamp, close, signal) = align(assets);
+    let (n, bars) = (assets.len(), timestamp.len());
+    let returns = close
+        .iter()
+        .map(|c| (0..bars).map(|t| if t == 0 { 0f64 } else { c[t] / c[t - 1] - 1f64 }).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let mut weights = vec![vec![0f64; bars]; n];
+    let mut contribution = vec![vec![0f64; bars]; n];
+    let mut turnover = vec![vec![0f64; bars]; n];
+    let mut book = vec![0f64; bars];
+    let mut target = vec![0f64; n]; // decided at the previous close
+    for t in 0..bars {
+        let mut gross = 0f64;
+        for i in 0..n {
+            let previous = if t == 0 { 0f64 } else { weights[i][t - 1] };
+            weights[i][t] = target[i];
+            turnover[i][t] = (target[i] - previous).abs();
+            contribution[i][t] = target[i] * returns[i][t] - turnover[i][t] * spec.fee;
+            gross += contribution[i][t];
+        }
+        book[t] = gross;
+
+        // the drift of the prices is left to the next rebalance
+        let start = (t + 1).saturating_sub(spec.window);
+        let trailing = returns.iter().map(|r| &r[start.max(1).min(t + 1)..t + 1]).collect::<Vec<_>>();
+        let signals = signal.iter().map(|s| s[t]).collect::<Vec<_>>();
+        target = allocate(&signals, &trailing, spec);
+    }
+
+    Portfolio {
+        symbols: assets.iter().map(|a| a.symbol.clone()).collect(),
+        timestamp,
+        weights,
+        contribution,
+        turnover,
+        returns: book,
+        interval: spec.interval,
+    }
+}
+
+impl Portfolio {
+    pub fn gross_exposure(&self) -> Vec<f64> {
+        (0..self.timestamp.len()).map(|t| self.weights.iter().map(|w| w[t].abs()).sum()).collect()
+    }
+
+    // of the book, the turnover of all the symbols together
+    pub fn metrics(&self) -> Metrics {
+        let turnover = (0..self.timestamp.len()).map(|t| self.turnover.iter().map(|w| w[t]).sum::<f64>()).collect::<Vec<_>>();
+        metrics(&self.returns, &self.gross_exposure(), &turnover, None, self.interval)
+    }
+
+    // of each symbol, from its contribution to the book
+    pub fn symbol_metrics(&self) -> Vec<(String, Metrics)> {
+        (0..self.symbols.len())
+            .map(|i| (self.symbols[i].clone(), metrics(&self.contribution[i], &self.weights[i], &self.turnover[i], None, self.interval)))
+            .collect()
+    }
+
+    // one row per metric, one column per symbol and `portfolio`
+    pub fn metrics_frame(&self) -> Result<DataFrame> {
+        let book = self.metrics();
+        let mut columns = vec![Series::new("metric", book.rows().iter().map(|(name, _)| *name).collect::<Vec<_>>())];
+        for (symbol, metrics) in self.symbol_metrics() {
+            columns.push(Series::new(&symbol, metrics.rows().into_iter().map(|(_, value)| value).collect::<Vec<_>>()));
+        }
+        columns.push(Series::new("portfolio", book.rows().into_iter().map(|(_, value)| value).collect::<Vec<_>>()));
+        DataFrame::new(columns)
+    }
+
+    // the weight of each symbol and the equity of the book
+    pub fn equity_frame(&self) -> Result<DataFrame> {
+        let mut columns = vec![timestamp_series("timestamp", self.timestamp.clone())?];
+        for (symbol, weights) in self.symbols.iter().zip(&self.weights) {
+            columns.push(Series::new(&format!("{} weight", symbol), weights.clone()));
+        }
+        columns.push(Series::new("gross exposure", self.gross_exposure()));
+        columns.push(Series::new("return", self.returns.clone()));
+        columns.push(Series::new("equity", equity(&self.returns)));
+        DataFrame::new(columns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthetic::{generate, Model, Regime, SyntheticSpec};
+
+    fn asset(symbol: &str, volatility: f64, seed: u64, signal: f64) -> Asset {
+        let df = generate(&SyntheticSpec::new(Model::Gbm(Regime { drift: 0.0, volatility }), 600, seed)).unwrap();
+        let timestamp = df.column("openTime").unwrap().i64().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        Asset { symbol: symbol.to_string(), timestamp, close: f64_column(&df, "close").unwrap(), signal: vec![signal; 600] }
+    }
+
+    #[test]
+    fn test_allocations() {
+        let assets = [asset("BTCUSDT", 0.01, 1, 1.0), asset("ETHUSDT", 0.02, 2, -0.5), asset("SOLUSDT", 0.01, 3, 0.25)];
+        let spec = PortfolioSpec::new(Allocation::EqualWeight, Interval::M15);
+        let portfolio = backtest_portfolio(&assets, &spec);
+        assert_eq!(portfolio.weights[1][0], 0.0);
+        assert!((portfolio.weights[1][1] + 1.0 / 3.0).abs() < 1e-12);
+        // the contributions add up to the book
+        let total = (0..600).map(|t| (0..3).map(|i| portfolio.contribution[i][t]).sum::<f64>()).collect::<Vec<_>>();
+        assert_eq!(total, portfolio.returns);
+
+        let proportional = backtest_portfolio(&assets, &PortfolioSpec { max_positions: Some(2), ..PortfolioSpec::new(Allocation::SignalProportional, Interval::M15) });
+        assert!((proportional.weights[0][1] - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(proportional.weights[2][1], 0.0);
+
+        // twice the volatility, half the weight
+        let inverse = backtest_portfolio(&assets, &PortfolioSpec { window: 400, ..PortfolioSpec::new(Allocation::InverseVolatility, Interval::M15) });
+        assert_eq!(inverse.weights[0][400], 0.0);
+        assert!((inverse.weights[0][500] / -inverse.weights[1][500] - 2.0).abs() < 0.3);
+        // independent assets: risk parity is inverse volatility
+        let parity = backtest_portfolio(&assets, &PortfolioSpec { window: 400, ..PortfolioSpec::new(Allocation::RiskParity, Interval::M15) });
+        assert!((parity.weights[0][500] - inverse.weights[0][500]).abs() < 0.03);
+
+        let frame = portfolio.metrics_frame().unwrap();
+        assert_eq!(frame.width(), 5);
+        // annualized by the interval of the spec, four times fewer hourly bars a year
+        let hourly = backtest_portfolio(&assets, &PortfolioSpec { interval: Interval::H1, ..spec });
+        assert!((portfolio.metrics().annual_volatility / hourly.metrics().annual_volatility - 2.0).abs() < 1e-9);
+        assert_eq!(portfolio.equity_frame().unwrap().width(), 7);
+    }
+
+    #[test]
+    fn test_correlation_cap() {
+        // the same market twice is one bet
+        let assets = [asset("BTCUSDT", 0.01, 1, 1.0), asset("BTCUSDC", 0.01, 1, 1.0), asset("ETHUSDT", 0.01, 2, 1.0)];
+        let cap = CorrelationCap { threshold: 0.8, cap: 0.4 };
+        let spec = PortfolioSpec { correlation_cap: Some(cap), ..PortfolioSpec::new(Allocation::EqualWeight, Interval::M15) };
+        let portfolio = backtest_portfolio(&assets, &spec);
+        let w = |i: usize| portfolio.weights[i][500];
+        assert!((w(0) + w(1) - 0.4).abs() < 1e-12);
+        assert!((w(2) - 1.0 / 3.0).abs() < 1e-12);
+
+        // a short of the same market hedges the long, each only capped on its own
+        let hedged = [asset("BTCUSDT", 0.01, 1, 1.0), asset("BTCUSDC", 0.01, 1, -1.0)];
+        let portfolio = backtest_portfolio(&hedged, &spec);
+        assert!((portfolio.weights[0][500] - 0.4).abs() < 1e-12);
+        assert!((portfolio.weights[1][500] + 0.4).abs() < 1e-12);
+        assert!(portfolio.returns[500].abs() < 1e-12);
+    }
+}
@@ -0,0 +1,246 @@
+use polars::prelude::*;
+
+use crate::data::Candles;
+use crate::portfolio::Portfolio;
+use crate::stats;
+
+// Value at risk and expected shortfall of bar returns, as positive losses at a confidence like 0.99,
+// and stress tests replaying crash windows of stored candles against the positions of a book.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarMethod {
+    Historical,
+    Parametric,    // normal
+    CornishFisher, // normal quantile corrected for the skewness and kurtosis of the sample
+}
+
+impl VarMethod {
+    pub const ALL: [VarMethod; 3] = [VarMethod::Historical, VarMethod::Parametric, VarMethod::CornishFisher];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VarMethod::Historical => "historical",
+            VarMethod::Parametric => "parametric",
+            VarMethod::CornishFisher => "cornish-fisher",
+        }
+    }
+}
+
+fn cornish_fisher(z: f64, skew: f64, excess: f64) -> f64 {
+    z + (z * z - 1f64) * skew / 6f64 + (z.powi(3) - 3f64 * z) * excess / 24f64 - (2f64 * z.powi(3) - 5f64 * z) * skew * skew / 36f64
+}
+
+// quantile of the returns at `p` by the method
+fn quantile(returns: &[f64], p: f64, method: VarMethod) -> f64 {
+    let (mean, std) = (stats::mean(returns), stats::std(returns));
+    match method {
+        VarMethod::Historical => stats::quantile(returns, p),
+        VarMethod::Parametric => mean + std * stats::normal_quantile(p),
+        VarMethod::CornishFisher => {
+            let (skew, excess) = (stats::skewness(returns), stats::kurtosis(returns) - 3f64);
+            mean + std * cornish_fisher(stats::normal_quantile(p), skew, excess)
+        }
+    }
+}
+
+fn finite(returns: &[f64]) -> Vec<f64> {
+    returns.iter().copied().filter(|r| r.is_finite()).collect()
+}
+
+pub fn value_at_risk(returns: &[f64], confidence: f64, method: VarMethod) -> f64 {
+    -quantile(&finite(returns), 1f64 - confidence, method)
+}
+
+// Mean loss beyond the value at risk: the tail of the sample for the historical method, the tail
+// quantiles averaged over the levels below 1 - confidence otherwise.
+pub fn expected_shortfall(returns: &[f64], confidence: f64, method: VarMethod) -> f64 {
+    let returns = finite(returns);
+    let alpha = 1f64 - confidence;
+    match method {
+        VarMethod::Historical => {
+            let var = quantile(&returns, alpha, method);
+            -stats::mean(&returns.iter().copied().filter(|r| *r <= var).collect::<Vec<_>>())
+        }
+        VarMethod::Parametric | VarMethod::CornishFisher => {
+            let levels = 200;
+            -(0..levels).map(|i| quantile(&returns, alpha * (i as f64 + 0.5) / levels as f64, method)).sum::<f64>() / levels as f64
+        }
+    }
+}
+
+// One row per series and `book` for their sum, a value at risk and expected shortfall column by method.
+pub fn risk_frame(series: &[(String, Vec<f64>)], confidence: f64) -> Result<DataFrame> {
+    let bars = series.iter().map(|(_, r)| r.len()).max().unwrap_or(0);
+    let book = (0..bars)
+        .map(|t| series.iter().filter_map(|(_, r)| r.get(t)).filter(|r| r.is_finite()).sum::<f64>())
+        .collect::<Vec<_>>();
+    let rows = series.iter().map(|(name, r)| (name.as_str(), r.as_slice())).chain([("book", book.as_slice())]).collect::<Vec<_>>();
+
+    let mut columns = vec![Series::new("symbol", rows.iter().map(|(name, _)| *name).collect::<Vec<_>>())];
+    for method in VarMethod::ALL {
+        let var = rows.iter().map(|(_, r)| value_at_risk(r, confidence, method)).collect::<Vec<_>>();
+        let es = rows.iter().map(|(_, r)| expected_shortfall(r, confidence, method)).collect::<Vec<_>>();
+        columns.push(Series::new(&format!("{} var", method.name()), var));
+        columns.push(Series::new(&format!("{} cvar", method.name()), es));
+    }
+    DataFrame::new(columns)
+}
+
+// of the contributions of each symbol to the returns of the book
+pub fn portfolio_risk(portfolio: &Portfolio, confidence: f64) -> Result<DataFrame> {
+    let series = portfolio.symbols.iter().cloned().zip(portfolio.contribution.iter().cloned()).collect::<Vec<_>>();
+    risk_frame(&series, confidence)
+}
+
+// A crash window, open times in ms with the end excluded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Scenario {
+    pub fn new(name: &str, start: i64, end: i64) -> Self {
+        Self { name: name.to_string(), start, end }
+    }
+}
+
+const DAY: i64 = 24 * 60 * 60 * 1000;
+
+pub fn crashes() -> Vec<Scenario> {
+    vec![
+        Scenario::new("covid", 1_583_971_200_000, 1_583_971_200_000 + 2 * DAY), // 2020-03-12
+        Scenario::new("china mining ban", 1_621_382_400_000, 1_621_382_400_000 + DAY), // 2021-05-19
+        Scenario::new("luna", 1_652_054_400_000, 1_652_054_400_000 + 4 * DAY), // 2022-05-09
+        Scenario::new("ftx", 1_667_865_600_000, 1_667_865_600_000 + 3 * DAY), // 2022-11-08
+    ]
+}
+
+// A position of the book, signed in quote currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exposure {
+    pub symbol: String,
+    pub notional: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stress {
+    pub scenario: String,
+    pub symbol: String, // `book` for the whole book
+    pub pnl: f64,       // at the end of the window
+    pub worst: f64,     // lowest pnl at a close within it
+}
+
+// The closes within the window marked against the first open of each symbol. Symbols without candles
+// in the window are left out, of the book too.
+pub fn stress_test(scenarios: &[Scenario], book: &[Exposure], candles: &[(String, Candles)]) -> Vec<Stress> {
+    let mut results = Vec::new();
+    for scenario in scenarios {
+        let mut paths: Vec<Vec<(i64, f64)>> = Vec::new();
+        for exposure in book {
+            let c = match candles.iter().find(|(symbol, _)| *symbol == exposure.symbol) {
+                Some((_, c)) => c,
+                None => continue,
+            };
+            let bars = (0..c.len()).filter(|i| c.timestamp[*i] >= scenario.start && c.timestamp[*i] < scenario.end).collect::<Vec<_>>();
+            let entry = match bars.first() {
+                Some(first) => c.open[*first],
+                None => continue,
+            };
+            let path = bars.iter().map(|i| (c.timestamp[*i], exposure.notional * (c.close[*i] / entry - 1f64))).collect::<Vec<_>>();
+            results.push(Stress {
+                scenario: scenario.name.clone(),
+                symbol: exposure.symbol.clone(),
+                pnl: path.last().map(|(_, p)| *p).unwrap_or(0f64),
+                worst: path.iter().map(|(_, p)| *p).fold(0f64, f64::min),
+            });
+            paths.push(path);
+        }
+        // the book at every close any symbol has, the others at their last close
+        let mut timestamps = paths.iter().flatten().map(|(t, _)| *t).collect::<Vec<_>>();
+        timestamps.sort_unstable();
+        timestamps.dedup();
+        let book_path = timestamps
+            .iter()
+            .map(|t| {
+                paths
+                    .iter()
+                    .map(|path| path.iter().take_while(|(at, _)| at <= t).last().map(|(_, p)| *p).unwrap_or(0f64))
+                    .sum::<f64>()
+            })
+            .collect::<Vec<_>>();
+        results.push(Stress {
+            scenario: scenario.name.clone(),
+            symbol: "book".to_string(),
+            pnl: book_path.last().copied().unwrap_or(0f64),
+            worst: book_path.iter().copied().fold(0f64, f64::min),
+        });
+    }
+    results
+}
+
+pub fn stress_to_frame(results: &[Stress]) -> Result<DataFrame> {
+    DataFrame::new(vec![
+        Series::new("scenario", results.iter().map(|r| r.scenario.as_str()).collect::<Vec<_>>()),
+        Series::new("symbol", results.iter().map(|r| r.symbol.as_str()).collect::<Vec<_>>()),
+        Series::new("pnl", results.iter().map(|r| r.pnl).collect::<Vec<_>>()),
+        Series::new("worst pnl", results.iter().map(|r| r.worst).collect::<Vec<_>>()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use crate::synthetic::{generate, Model, Regime, SyntheticSpec};
+
+    #[test]
+    fn test_var() {
+        let mut rng = Rng::new(11);
+        let normal = (0..20_000).map(|_| 0.01 * rng.normal()).collect::<Vec<_>>();
+        // 2.326 standard deviations at 99%, and 2.665 for the mean beyond
+        for method in VarMethod::ALL {
+            assert!((value_at_risk(&normal, 0.99, method) - 0.02326).abs() < 0.001, "{}", method.name());
+            assert!((expected_shortfall(&normal, 0.99, method) - 0.02665).abs() < 0.0015, "{}", method.name());
+        }
+
+        // rare large losses: the normal misses them, the correction catches some
+        let crashes = (0..20_000).map(|i| if i % 100 == 0 { -0.05 } else { 0.0005 + 0.005 * rng.normal() }).collect::<Vec<_>>();
+        let historical = expected_shortfall(&crashes, 0.99, VarMethod::Historical);
+        let parametric = expected_shortfall(&crashes, 0.99, VarMethod::Parametric);
+        let corrected = expected_shortfall(&crashes, 0.99, VarMethod::CornishFisher);
+        assert!(parametric < corrected && corrected < historical * 1.5);
+        assert!(value_at_risk(&crashes, 0.99, VarMethod::Historical) <= historical);
+
+        let frame = risk_frame(&[("BTCUSDT".to_string(), normal.clone()), ("ETHUSDT".to_string(), normal)], 0.99).unwrap();
+        assert_eq!(frame.shape(), (3, 7));
+        // perfectly correlated symbols add up
+        let var = frame.column("historical var").unwrap().f64().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert!((var[2] - 2.0 * var[0]).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_stress() {
+        let scenario = crashes().into_iter().find(|s| s.name == "luna").unwrap();
+        let market = |drift: f64, seed: u64| {
+            let spec = SyntheticSpec { start: scenario.start - 2 * DAY, ..SyntheticSpec::new(Model::Gbm(Regime { drift, volatility: 0.002 }), 8 * 96, seed) };
+            let mut df = generate(&spec).unwrap();
+            df.rename("openTime", "timestamp").unwrap();
+            Candles::from_frame(&df).unwrap()
+        };
+        let candles = vec![("BTCUSDT".to_string(), market(-0.002, 1)), ("ETHUSDT".to_string(), market(-0.003, 2))];
+        let book = vec![
+            Exposure { symbol: "BTCUSDT".to_string(), notional: 10_000.0 },
+            Exposure { symbol: "ETHUSDT".to_string(), notional: -5_000.0 },
+            Exposure { symbol: "SOLUSDT".to_string(), notional: 1_000.0 },
+        ];
+        let results = stress_test(&[scenario], &book, &candles);
+        assert_eq!(results.iter().map(|r| r.symbol.as_str()).collect::<Vec<_>>(), vec!["BTCUSDT", "ETHUSDT", "book"]);
+        // the long loses in the crash and the short gains
+        assert!(results[0].pnl < -5_000.0 && results[1].pnl > 2_500.0);
+        assert!((results[2].pnl - results[0].pnl - results[1].pnl).abs() < 1e-6);
+        assert!(results.iter().all(|r| r.worst <= r.pnl.min(0.0)));
+        assert_eq!(stress_to_frame(&results).unwrap().height(), 3);
+    }
+}
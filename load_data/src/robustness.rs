@@ -0,0 +1,173 @@
+use polars::prelude::*;
+
+use crate::backtest::{backtest, BacktestSpec, Fill};
+use crate::data::{bool_column, f64_column, signal, Candles, Interval};
+use crate::metrics::{drawdown, equity};
+use crate::random::Rng;
+use crate::signal::{positions, SignalSpec};
+use crate::stats;
+
+// Robustness of a backtest beyond its one historical path: resampled trade sequences, block bootstrap
+// of bar returns, and a null model firing the same number of entries as the signal at random bars.
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Distribution {
+    pub final_equity: Vec<f64>,
+    pub max_drawdown: Vec<f64>,
+    pub sharpe: Vec<f64>, // annualized
+}
+
+impl Distribution {
+    fn push(&mut self, returns: &[f64], periods: f64) {
+        let equity = equity(returns);
+        self.final_equity.push(equity.last().copied().unwrap_or(1f64));
+        self.max_drawdown.push(drawdown(&equity).into_iter().fold(0f64, f64::min));
+        self.sharpe.push(stats::mean(returns) / stats::std(returns) * periods.sqrt());
+    }
+
+    // one row per statistic, one column per quantile
+    pub fn bands(&self, quantiles: &[f64]) -> Result<DataFrame> {
+        let statistics = [("final equity", &self.final_equity), ("max drawdown", &self.max_drawdown), ("sharpe", &self.sharpe)];
+        let mut columns = vec![Series::new("statistic", statistics.iter().map(|(name, _)| *name).collect::<Vec<_>>())];
+        for q in quantiles {
+            let values = statistics.iter().map(|(_, sample)| stats::quantile(sample, *q)).collect::<Vec<_>>();
+            columns.push(Series::new(&format!("q{}", (q * 100f64).round()), values));
+        }
+        DataFrame::new(columns)
+    }
+}
+
+// Trade sequences in random order (`replace` = false) or drawn with replacement, each trade return
+// compounded in turn. `periods` is the number of trades a year for the Sharpe ratio.
+pub fn resample_trades(trades: &[f64], paths: usize, replace: bool, periods: f64, seed: u64) -> Distribution {
+    let mut rng = Rng::new(seed);
+    let mut distribution = Distribution::default();
+    let mut path = trades.to_vec();
+    for _ in 0..paths {
+        if replace {
+            path.iter_mut().for_each(|t| *t = trades[rng.below(trades.len())]);
+        } else {
+            rng.shuffle(&mut path);
+        }
+        distribution.push(&path, periods);
+    }
+    distribution
+}
+
+// Circular block bootstrap of bar returns, blocks of `block` bars keep the autocorrelation within them.
+pub fn block_bootstrap(returns: &[f64], block: usize, paths: usize, interval: Interval, seed: u64) -> Distribution {
+    let mut rng = Rng::new(seed);
+    let mut distribution = Distribution::default();
+    let (n, block) = (returns.len(), block.max(1));
+    let mut path = Vec::with_capacity(n);
+    for _ in 0..paths {
+        path.clear();
+        while path.len() < n {
+            let start = rng.below(n);
+            path.extend((0..block.min(n - path.len())).map(|i| returns[(start + i) % n]));
+        }
+        distribution.push(&path, interval.periods_per_year());
+    }
+    distribution
+}
+
+#[derive(Debug, Clone)]
+pub struct NullModel {
+    pub events: usize,
+    pub actual: f64,      // total return of the signal
+    pub random: Vec<f64>, // total return of each random path
+    pub p_value: f64,     // share of random paths doing at least as well
+}
+
+fn total_return(df: DataFrame, position: Vec<f64>, fill: Fill) -> Result<f64> {
+    let mut df = df;
+    df.with_column(Series::new("position", position))?;
+    let df = backtest(df.lazy(), &BacktestSpec::new("position", fill)).collect()?;
+    Ok(f64_column(&df, "equity")?.last().copied().unwrap_or(1f64) - 1f64)
+}
+
+// Random entry null model of the output of `aggregate`: as many events as the primary signal at
+// random bars, taking the sides of the actual events in random order and exited by the same rule.
+pub fn random_entry(df: &DataFrame, spec: &SignalSpec, fill: Fill, paths: usize, seed: u64) -> Result<NullModel> {
+    let candles = Candles::from_frame(df)?;
+    let flags = df
+        .clone()
+        .lazy()
+        .select([signal().alias("signal"), col("upper band touched")])
+        .collect()?;
+    let events = bool_column(&flags, "signal")?;
+    let upper_touched = bool_column(&flags, "upper band touched")?;
+    let base = df.select(["timestamp", "open", "close"])?;
+    let actual = total_return(base.clone(), positions(&candles, &events, &upper_touched, spec), fill)?;
+
+    let mut sides = (0..events.len()).filter(|i| events[*i]).map(|i| upper_touched[i]).collect::<Vec<_>>();
+    let mut rng = Rng::new(seed);
+    let mut bars = (0..candles.len()).collect::<Vec<_>>();
+    let mut random = Vec::with_capacity(paths);
+    for _ in 0..paths {
+        rng.shuffle(&mut bars);
+        rng.shuffle(&mut sides);
+        let (mut events, mut upper) = (vec![false; candles.len()], vec![false; candles.len()]);
+        for (bar, side) in bars.iter().zip(&sides) {
+            events[*bar] = true;
+            upper[*bar] = *side;
+        }
+        random.push(total_return(base.clone(), positions(&candles, &events, &upper, spec), fill)?);
+    }
+
+    let beaten = random.iter().filter(|r| **r >= actual).count();
+    Ok(NullModel { events: sides.len(), actual, p_value: (beaten + 1) as f64 / (paths + 1) as f64, random })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resampling() {
+        let trades = [0.02, -0.01, 0.03, -0.02, 0.01];
+        let shuffled = resample_trades(&trades, 200, false, 250.0, 1);
+        // the order changes the drawdown, never the final equity
+        let product = trades.iter().fold(1.0, |e, t| e * (1.0 + t));
+        assert!(shuffled.final_equity.iter().all(|e| (e - product).abs() < 1e-12));
+        assert!(stats::quantile(&shuffled.max_drawdown, 0.05) < stats::quantile(&shuffled.max_drawdown, 0.95));
+        assert_eq!(resample_trades(&trades, 50, true, 250.0, 2), resample_trades(&trades, 50, true, 250.0, 2));
+
+        let mut rng = Rng::new(9);
+        let returns = (0..500).map(|_| 0.001 + 0.01 * rng.normal()).collect::<Vec<_>>();
+        let bootstrap = block_bootstrap(&returns, 20, 300, Interval::D1, 3);
+        assert_eq!(bootstrap.sharpe.len(), 300);
+        let bands = bootstrap.bands(&[0.05, 0.5, 0.95]).unwrap();
+        assert_eq!(bands.shape(), (3, 4));
+        let sharpe = bands.column("q50").unwrap().f64().unwrap().get(2).unwrap();
+        assert!((sharpe - 0.1 * 365f64.sqrt()).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_random_entry() {
+        // the price jumps right after every event, which random entries can't catch
+        let n = 300;
+        let events = (0..n).map(|i| i % 30 == 10).collect::<Vec<_>>();
+        let mut close = vec![100.0];
+        for i in 1..n {
+            let r = if i % 30 == 12 { 0.02 } else if i % 2 == 0 { 0.001 } else { -0.001 };
+            close.push(close[i - 1] * (1.0 + r));
+        }
+        let df = df![
+            "timestamp" => (0..n as i64).collect::<Vec<_>>(),
+            "open" => close.iter().enumerate().map(|(i, c)| if i == 0 { *c } else { close[i - 1] }).collect::<Vec<_>>(),
+            "high" => close.clone(),
+            "low" => close.clone(),
+            "close" => close.clone(),
+            "volume" => vec![1.0; n],
+            "abnormal volume" => events.clone(),
+            "upper band touched" => events.clone(),
+            "lower band touched" => vec![false; n],
+        ].unwrap();
+        let spec = SignalSpec { hold: 3, target_pnl: 1.0, ..Default::default() };
+        let null = random_entry(&df, &spec, Fill::NextOpen, 99, 4).unwrap();
+        assert_eq!(null.events, 10);
+        assert!(null.actual > 0.15);
+        assert!(null.p_value < 0.05);
+    }
+}
@@ -0,0 +1,92 @@
+use polars::prelude::*;
+
+use crate::data::{aggregate, bool_column, signal, Candles};
+use crate::meta_labeling::BandSide;
+
+// Parameters of `aggregate` together with the rule turning its primary signal into a target position.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SignalSpec {
+    pub sigma: f64,
+    pub target_pnl: f64,
+    pub duration: i64,
+    pub hold: usize, // bars a trade is held at most
+    pub band_side: BandSide,
+}
+
+impl Default for SignalSpec {
+    fn default() -> Self {
+        Self { sigma: 2.0, target_pnl: 0.01, duration: 20, hold: 20, band_side: BandSide::Momentum }
+    }
+}
+
+impl SignalSpec {
+    pub fn aggregate(&self, lf: LazyFrame) -> LazyFrame {
+        aggregate(lf, self.sigma, self.target_pnl, self.duration)
+    }
+}
+
+// Target position after the close of each bar: at an event the side picked by the band, held until
+// the close moved `target_pnl` for or against the trade or for `hold` bars.
+pub fn positions(candles: &Candles, events: &[bool], upper_touched: &[bool], spec: &SignalSpec) -> Vec<f64> {
+    let mut positions = vec![0f64; candles.len()];
+    let mut open: Option<(f64, f64, usize)> = None; // side, entry close and entry bar
+    for i in 0..candles.len() {
+        if let Some((side, entry, since)) = open {
+            let pnl = side * (candles.close[i] / entry - 1f64);
+            if pnl.abs() >= spec.target_pnl || i - since >= spec.hold {
+                open = None;
+            } else {
+                positions[i] = side;
+                continue;
+            }
+        }
+        if events[i] {
+            let side = spec.band_side.side(upper_touched[i]);
+            open = Some((side, candles.close[i], i));
+            positions[i] = side;
+        }
+    }
+    positions
+}
+
+// adds the `position` column to the output of `aggregate`
+pub fn with_position(df: &DataFrame, spec: &SignalSpec) -> Result<DataFrame> {
+    let candles = Candles::from_frame(df)?;
+    let flags = df
+        .clone()
+        .lazy()
+        .select([signal().alias("signal"), col("upper band touched")])
+        .collect()?;
+    let events = bool_column(&flags, "signal")?;
+    let upper_touched = bool_column(&flags, "upper band touched")?;
+
+    let mut df = df.clone();
+    df.with_column(Series::new("position", positions(&candles, &events, &upper_touched, spec)))?;
+    Ok(df)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions() {
+        let candles = Candles {
+            timestamp: (0..8).collect(),
+            open: vec![100.0; 8],
+            high: vec![100.0; 8],
+            low: vec![100.0; 8],
+            close: vec![100.0, 100.5, 101.2, 101.0, 100.0, 100.0, 100.0, 100.0],
+            volume: vec![1.0; 8],
+        };
+        let events = [true, false, false, true, false, false, false, false];
+        let upper = [true, false, false, false, false, false, false, false];
+        let spec = SignalSpec { target_pnl: 0.01, hold: 3, ..Default::default() };
+
+        // long at the upper band until the close is 1% up, then short at the lower band for 3 bars
+        assert_eq!(positions(&candles, &events, &upper, &spec), vec![1.0, 1.0, 0.0, -1.0, -1.0, -1.0, 0.0, 0.0]);
+        let spec = SignalSpec { band_side: BandSide::Reversion, ..spec };
+        assert_eq!(positions(&candles, &events, &upper, &spec)[..3], [-1.0, -1.0, 0.0]);
+    }
+}
@@ -0,0 +1,298 @@
+use polars::prelude::*;
+
+use crate::data::{f64_column, Candles, Interval, Trend};
+use crate::engine::{Bar, Context, Order, Side, Strategy};
+use crate::stats;
+
+// Position sizing: turns a direction (-1..1, or a predicted trend) into a target leverage, the notional
+// over the equity, decided at the close of each bar like the position of `backtest`.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Volatility {
+    Atr(usize),      // average true range over the window as a fraction of the close, over `RANGE_TO_STD`
+    Realized(usize), // std of the close to close returns over the window
+}
+
+// Mean range of a brownian bar over the std of its return, sqrt(8 / pi), so that the ATR estimates the
+// same std as the realized volatility. Bars with gaps or fat tails have wider ranges and size smaller.
+pub const RANGE_TO_STD: f64 = 1.5957691216057308;
+
+impl Volatility {
+    // std of the bar returns, known at the close of each bar
+    pub fn estimate(&self, candles: &Candles) -> Vec<f64> {
+        match self {
+            Volatility::Atr(window) => atr(candles, *window).into_iter().map(|atr| atr / RANGE_TO_STD).collect(),
+            Volatility::Realized(window) => realized_volatility(candles, *window),
+        }
+    }
+}
+
+pub fn atr(candles: &Candles, window: usize) -> Vec<f64> {
+    let n = candles.len();
+    let true_range = (0..n)
+        .map(|i| {
+            let range = candles.high[i] - candles.low[i];
+            if i == 0 {
+                return range;
+            }
+            let previous = candles.close[i - 1];
+            range.max((candles.high[i] - previous).abs()).max((candles.low[i] - previous).abs())
+        })
+        .collect::<Vec<_>>();
+    (0..n)
+        .map(|i| {
+            if window == 0 || i < window {
+                return f64::NAN;
+            }
+            stats::mean(&true_range[i + 1 - window..=i]) / candles.close[i]
+        })
+        .collect()
+}
+
+pub fn realized_volatility(candles: &Candles, window: usize) -> Vec<f64> {
+    let n = candles.len();
+    let returns = (0..n)
+        .map(|i| if i == 0 { f64::NAN } else { candles.close[i] / candles.close[i - 1] - 1f64 })
+        .collect::<Vec<_>>();
+    (0..n)
+        .map(|i| {
+            if window < 2 || i < window {
+                return f64::NAN;
+            }
+            stats::std(&returns[i + 1 - window..=i])
+        })
+        .collect()
+}
+
+// Win rate and average win and loss of the trades, as returns on the position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub win_rate: f64,
+    pub average_win: f64,
+    pub average_loss: f64, // positive
+}
+
+impl Edge {
+    // e.g. from `round_trips` of a backtest at a leverage of 1
+    pub fn from_trades(trades: &[f64]) -> Self {
+        let wins = trades.iter().copied().filter(|t| *t > 0f64).collect::<Vec<_>>();
+        let losses = trades.iter().filter(|t| **t < 0f64).map(|t| -t).collect::<Vec<_>>();
+        Self {
+            win_rate: wins.len() as f64 / trades.len() as f64,
+            average_win: stats::mean(&wins),
+            average_loss: stats::mean(&losses),
+        }
+    }
+
+    pub fn payoff(&self) -> f64 {
+        self.average_win / self.average_loss
+    }
+
+    // leverage maximizing the expected log growth, p / loss - q / win
+    pub fn kelly(&self) -> f64 {
+        let kelly = self.win_rate / self.average_loss - (1f64 - self.win_rate) / self.average_win;
+        if kelly.is_finite() {
+            kelly.max(0f64)
+        } else {
+            0f64
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sizing {
+    Fixed(f64),                                               // the same leverage on every trade
+    FixedFractional { risk: f64, stop: f64 },                 // lose `risk` of the equity at a stop `stop` away
+    VolatilityTarget { target: f64, volatility: Volatility }, // annualized volatility aimed at
+    Kelly { edge: Edge, fraction: f64 },                      // a fraction of the Kelly leverage
+}
+
+impl Sizing {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sizing::Fixed(_) => "Fixed",
+            Sizing::FixedFractional { .. } => "FixedFractional",
+            Sizing::VolatilityTarget { .. } => "VolatilityTarget",
+            Sizing::Kelly { .. } => "Kelly",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SizingSpec {
+    pub sizing: Sizing,
+    pub max_leverage: f64,
+    pub interval: Interval, // annualizes the volatility
+}
+
+impl SizingSpec {
+    pub fn new(sizing: Sizing, interval: Interval) -> Self {
+        Self { sizing, max_leverage: 3f64, interval }
+    }
+}
+
+// Leverage of a full position at each bar before the cap, 0 while the volatility is not known yet.
+pub fn leverage(candles: &Candles, spec: &SizingSpec) -> Vec<f64> {
+    let n = candles.len();
+    let leverage = match spec.sizing {
+        Sizing::Fixed(leverage) => vec![leverage; n],
+        Sizing::FixedFractional { risk, stop } => vec![risk / stop; n],
+        Sizing::VolatilityTarget { target, volatility } => {
+            let per_bar = target / spec.interval.periods_per_year().sqrt();
+            volatility.estimate(candles).into_iter().map(|vol| per_bar / vol).collect()
+        }
+        Sizing::Kelly { edge, fraction } => vec![fraction * edge.kelly(); n],
+    };
+    leverage.into_iter().map(|l| if l.is_finite() { l } else { 0f64 }).collect()
+}
+
+// the direction scaled by the leverage and capped at `max_leverage`
+pub fn size(candles: &Candles, direction: &[f64], spec: &SizingSpec) -> Vec<f64> {
+    leverage(candles, spec)
+        .into_iter()
+        .zip(direction)
+        .map(|(leverage, d)| {
+            let d = if d.is_finite() { d.clamp(-1f64, 1f64) } else { 0f64 };
+            (d * leverage).clamp(-spec.max_leverage, spec.max_leverage)
+        })
+        .collect()
+}
+
+// numeric columns as they are, trends as 1 for Bull and -1 for Bear
+pub fn direction_column(df: &DataFrame, name: &str) -> Result<Vec<f64>> {
+    let column = df.column(name)?;
+    match column.dtype() {
+        DataType::Categorical(_) | DataType::Utf8 => Ok(Trend::from_series(column)?
+            .into_iter()
+            .map(|trend| match trend {
+                Some(Trend::Bull) => 1f64,
+                Some(Trend::Bear) => -1f64,
+                _ => 0f64,
+            })
+            .collect()),
+        _ => f64_column(df, name),
+    }
+}
+
+// Adds the `sized position` column from the `direction` column, for `backtest` or `Rebalance`. Note
+// that the trend columns of `aggregate` are labels, a direction has to be predicted from them.
+pub fn with_size(df: &DataFrame, direction: &str, spec: &SizingSpec) -> Result<DataFrame> {
+    let candles = Candles::from_frame(df)?;
+    let sized = size(&candles, &direction_column(df, direction)?, spec);
+    let mut df = df.clone();
+    df.with_column(Series::new("sized position", sized))?;
+    Ok(df)
+}
+
+// Strategy of the engine trading at each close to the target leverage of a feature column, e.g.
+// `sized position`, with market orders at the next open.
+pub struct Rebalance {
+    pub column: String,
+    pub threshold: f64, // smallest change of leverage traded
+}
+
+impl Rebalance {
+    pub fn new(column: &str) -> Self {
+        Self { column: column.to_string(), threshold: 0.05 }
+    }
+}
+
+impl Strategy for Rebalance {
+    fn on_bar(&mut self, bar: &Bar, ctx: &mut Context) {
+        let target = bar.feature(&self.column).filter(|t| t.is_finite()).unwrap_or(0f64);
+        let equity = ctx.equity();
+        if equity <= 0f64 {
+            return;
+        }
+        let current = ctx.position() * bar.close / equity;
+        let change = target - current;
+        if change.abs() < self.threshold && !(target == 0f64 && ctx.position() != 0f64) {
+            return;
+        }
+        ctx.cancel_all();
+        let quantity = if target == 0f64 { ctx.position().abs() } else { change.abs() * equity / bar.close };
+        let side = if change > 0f64 { Side::Buy } else { Side::Sell };
+        ctx.submit(Order::market(side, quantity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtest::{backtest, BacktestSpec, Fill};
+    use crate::engine::{run, EngineSpec};
+    use crate::synthetic::{generate, Model, Regime, SyntheticSpec};
+
+    // fine intrabar steps, the range of a few steps being narrower than a brownian one
+    fn candles(volatility: f64, seed: u64) -> DataFrame {
+        let spec = SyntheticSpec { steps: 512, ..SyntheticSpec::new(Model::Gbm(Regime { drift: 0.0, volatility }), 3000, seed) };
+        let df = generate(&spec).unwrap().lazy();
+        df.with_columns([col("openTime").alias("timestamp"), lit(1f64).alias("direction")]).collect().unwrap()
+    }
+
+    #[test]
+    fn test_volatility_target() {
+        let per_bar = 0.5 / Interval::M15.periods_per_year().sqrt();
+        for estimator in [Volatility::Realized(100), Volatility::Atr(100)] {
+            let spec = SizingSpec::new(Sizing::VolatilityTarget { target: 0.5, volatility: estimator }, Interval::M15);
+            for volatility in [0.001, 0.004] {
+                let df = with_size(&candles(volatility, 1), "direction", &spec).unwrap();
+                let df = backtest(df.lazy(), &BacktestSpec::new("sized position", Fill::Close)).collect().unwrap();
+                // the realized volatility lands on the target whatever the volatility of the market
+                let returns = f64_column(&df, "strategy return").unwrap();
+                assert!((stats::std(&returns[200..]) / per_bar - 1.0).abs() < 0.1, "{:?} {}", estimator, volatility);
+            }
+        }
+        let spec = SizingSpec::new(Sizing::VolatilityTarget { target: 0.5, volatility: Volatility::Realized(100) }, Interval::M15);
+
+        // the cap binds when the market is quiet
+        let capped = SizingSpec { max_leverage: 2.0, ..spec };
+        let sized = size(&Candles::from_frame(&candles(0.0001, 2)).unwrap(), &vec![-1.0; 3000], &capped);
+        assert_eq!(sized[0], 0.0);
+        assert_eq!(sized[2999], -2.0);
+
+        let df = candles(0.002, 3);
+        let candles = Candles::from_frame(&df).unwrap();
+        // the true range is wider than the close to close move
+        assert!(stats::mean(&atr(&candles, 50)[50..]) > stats::mean(&realized_volatility(&candles, 50)[50..]));
+    }
+
+    #[test]
+    fn test_kelly() {
+        let edge = Edge::from_trades(&[0.02, 0.02, 0.02, -0.01, -0.01]);
+        assert_eq!(edge.win_rate, 0.6);
+        assert!((edge.payoff() - 2.0).abs() < 1e-12);
+        // 0.6 / 0.01 - 0.4 / 0.02
+        assert!((edge.kelly() - 40.0).abs() < 1e-9);
+        assert_eq!(Edge::from_trades(&[0.01, -0.02]).kelly(), 0.0);
+
+        let spec = SizingSpec { max_leverage: 5.0, ..SizingSpec::new(Sizing::Kelly { edge, fraction: 0.1 }, Interval::H1) };
+        let df = df![
+            "timestamp" => [0i64, 1, 2],
+            "open" => [1.0, 1.0, 1.0],
+            "high" => [1.0, 1.0, 1.0],
+            "low" => [1.0, 1.0, 1.0],
+            "close" => [1.0, 1.0, 1.0],
+            "volume" => [1.0, 1.0, 1.0],
+            "trend" => ["Bull", "Unknown", "Bear"],
+        ]
+        .unwrap();
+        let df = with_size(&df, "trend", &spec).unwrap();
+        assert_eq!(f64_column(&df, "sized position").unwrap(), vec![4.0, 0.0, -4.0]);
+    }
+
+    #[test]
+    fn test_rebalance() {
+        let spec = SizingSpec::new(Sizing::FixedFractional { risk: 0.01, stop: 0.02 }, Interval::M15);
+        let mut df = candles(0.002, 4).slice(0, 100);
+        let direction = (0..100).map(|i| if i < 50 { 1.0 } else { 0.0 }).collect::<Vec<_>>();
+        df.with_column(Series::new("direction", direction)).unwrap();
+        let df = with_size(&df, "direction", &spec).unwrap();
+        let report = run(&df, &mut Rebalance::new("sized position"), &EngineSpec::default()).unwrap();
+        // half the equity long from the second bar, flat after the 50th
+        let open = f64_column(&df, "open").unwrap();
+        assert!((report.fills[0].quantity * open[1] / 10000.0 - 0.5).abs() < 0.01);
+        assert!(report.positions[49] > 0.0);
+        assert_eq!(report.positions[99], 0.0);
+    }
+}
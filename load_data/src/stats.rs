@@ -0,0 +1,131 @@
+// Small numeric helpers shared by the studies, NaN on empty samples.
+
+pub fn mean(sample: &[f64]) -> f64 {
+    if sample.is_empty() {
+        return f64::NAN;
+    }
+    sample.iter().sum::<f64>() / sample.len() as f64
+}
+
+// sample standard deviation, ddof = 1
+pub fn std(sample: &[f64]) -> f64 {
+    if sample.len() < 2 {
+        return f64::NAN;
+    }
+    let m = mean(sample);
+    (sample.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / (sample.len() - 1) as f64).sqrt()
+}
+
+// linear interpolation between the closest ranks
+pub fn quantile(sample: &[f64], q: f64) -> f64 {
+    if sample.is_empty() {
+        return f64::NAN;
+    }
+    let mut sorted = sample.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let at = q.clamp(0f64, 1f64) * (sorted.len() - 1) as f64;
+    let (lo, hi) = (at.floor() as usize, at.ceil() as usize);
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (at - lo as f64)
+}
+
+pub fn median(sample: &[f64]) -> f64 {
+    quantile(sample, 0.5)
+}
+
+// sample skewness, biased
+pub fn skewness(sample: &[f64]) -> f64 {
+    let (m, n) = (mean(sample), sample.len() as f64);
+    let m2 = sample.iter().map(|x| (x - m).powi(2)).sum::<f64>() / n;
+    let m3 = sample.iter().map(|x| (x - m).powi(3)).sum::<f64>() / n;
+    m3 / m2.powf(1.5)
+}
+
+// sample kurtosis, not in excess, 3 for a normal sample
+pub fn kurtosis(sample: &[f64]) -> f64 {
+    let (m, n) = (mean(sample), sample.len() as f64);
+    let m2 = sample.iter().map(|x| (x - m).powi(2)).sum::<f64>() / n;
+    let m4 = sample.iter().map(|x| (x - m).powi(4)).sum::<f64>() / n;
+    m4 / (m2 * m2)
+}
+
+pub fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let (ma, mb) = (mean(a), mean(b));
+    let cov = a.iter().zip(b).map(|(x, y)| (x - ma) * (y - mb)).sum::<f64>();
+    let va = a.iter().map(|x| (x - ma).powi(2)).sum::<f64>();
+    let vb = b.iter().map(|y| (y - mb).powi(2)).sum::<f64>();
+    cov / (va * vb).sqrt()
+}
+
+pub fn normal_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2f64 * std::f64::consts::PI).sqrt()
+}
+
+// Abramowitz and Stegun 7.1.26 on erf, good to 1e-7
+pub fn normal_cdf(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1f64 / (1f64 + 0.3275911 * z);
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1f64 - poly * (-z * z).exp();
+    if x >= 0f64 {
+        0.5 * (1f64 + erf)
+    } else {
+        0.5 * (1f64 - erf)
+    }
+}
+
+// Acklam's rational approximation of the inverse of the normal cdf
+#[allow(clippy::excessive_precision)]
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e1, 2.209460984245205e2, -2.759285104469687e2, 1.383577518672690e2, -3.066479806614716e1, 2.506628277459239];
+    const B: [f64; 5] = [-5.447609879822406e1, 1.615858368580409e2, -1.556989798598866e2, 6.680131188771972e1, -1.328068155288572e1];
+    const C: [f64; 6] = [-7.784894002430293e-3, -3.223964580411365e-1, -2.400758277161838, -2.549732539343734, 4.374664141464968, 2.938163982698783];
+    const D: [f64; 4] = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+    const LOW: f64 = 0.02425;
+
+    if p <= 0f64 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1f64 {
+        return f64::INFINITY;
+    }
+    if p < LOW {
+        let q = (-2f64 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1f64)
+    } else if p <= 1f64 - LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1f64)
+    } else {
+        -normal_quantile(1f64 - p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normal() {
+        assert!((normal_cdf(0f64) - 0.5).abs() < 1e-9);
+        assert!((normal_cdf(1.959963985) - 0.975).abs() < 1e-6);
+        assert!((normal_quantile(0.975) - 1.959963985).abs() < 1e-6);
+        assert!((normal_quantile(0.001) + 3.090232306).abs() < 1e-6);
+        for p in [0.01, 0.2, 0.5, 0.9, 0.999] {
+            assert!((normal_cdf(normal_quantile(p)) - p).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_sample() {
+        let sample = [3.0, 1.0, 4.0, 1.0, 5.0];
+        assert_eq!(mean(&sample), 2.8);
+        assert!((std(&sample) - 1.788854382).abs() < 1e-9);
+        assert_eq!(median(&sample), 3.0);
+        assert_eq!(quantile(&sample, 0.25), 1.0);
+        assert!(mean(&[]).is_nan());
+        assert!(skewness(&[1.0, 2.0, 3.0]).abs() < 1e-12);
+        assert!((kurtosis(&[1.0, -1.0, 1.0, -1.0]) - 1.0).abs() < 1e-12);
+        assert!((correlation(&[1.0, 2.0, 3.0], &[2.0, 4.0, 7.0]) - 0.9933992678).abs() < 1e-9);
+    }
+}
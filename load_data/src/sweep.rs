@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use polars::prelude::*;
+
+use crate::backtest::{backtest, with_costs, BacktestSpec, Fill};
+use crate::costs::CostModel;
+use crate::data::{f64_column, rolling_stats, signals, Interval};
+use crate::meta_labeling::BandSide;
+use crate::metrics::{metrics_from_frame, Metrics};
+use crate::overfitting::{deflated_sharpe, probabilistic_sharpe, probability_of_overfitting};
+use crate::random::Rng;
+use crate::signal::{with_position, SignalSpec};
+
+// Parameter sweep of `SignalSpec` through `aggregate`, the signal position and `backtest`,
+// run across threads. The rolling stats only depend on the duration so they are computed once per duration.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignalGrid {
+    pub sigma: Vec<f64>,
+    pub target_pnl: Vec<f64>,
+    pub duration: Vec<i64>,
+    pub hold: Vec<usize>,
+    pub band_side: Vec<BandSide>,
+}
+
+impl Default for SignalGrid {
+    fn default() -> Self {
+        let spec = SignalSpec::default();
+        Self {
+            sigma: vec![spec.sigma],
+            target_pnl: vec![spec.target_pnl],
+            duration: vec![spec.duration],
+            hold: vec![spec.hold],
+            band_side: vec![spec.band_side],
+        }
+    }
+}
+
+impl SignalGrid {
+    // every combination, grouped by duration
+    pub fn specs(&self) -> Vec<SignalSpec> {
+        let mut specs = Vec::new();
+        for &duration in &self.duration {
+            for &sigma in &self.sigma {
+                for &target_pnl in &self.target_pnl {
+                    for &hold in &self.hold {
+                        for &band_side in &self.band_side {
+                            specs.push(SignalSpec { sigma, target_pnl, duration, hold, band_side });
+                        }
+                    }
+                }
+            }
+        }
+        specs
+    }
+
+    // random search: `n` specs drawn uniformly between the min and max of each field, an error when a
+    // field has no values to draw from
+    pub fn sample(&self, n: usize, seed: u64) -> Result<Vec<SignalSpec>> {
+        fn bounds<T: Copy + PartialOrd>(name: &str, values: &[T]) -> Result<(T, T)> {
+            let first = *values.first().ok_or_else(|| PolarsError::NoData(format!("no {} to sample", name).into()))?;
+            let min = values.iter().copied().fold(first, |a, b| if b < a { b } else { a });
+            let max = values.iter().copied().fold(first, |a, b| if b > a { b } else { a });
+            Ok((min, max))
+        }
+        let (sigma, target_pnl) = (bounds("sigma", &self.sigma)?, bounds("target pnl", &self.target_pnl)?);
+        let (duration, hold) = (bounds("duration", &self.duration)?, bounds("hold", &self.hold)?);
+        if self.band_side.is_empty() {
+            return Err(PolarsError::NoData("no band side to sample".into()));
+        }
+        let mut rng = Rng::new(seed);
+        let mut specs = (0..n)
+            .map(|_| SignalSpec {
+                sigma: rng.range(sigma.0, sigma.1),
+                target_pnl: rng.range(target_pnl.0, target_pnl.1),
+                duration: duration.0 + rng.below(duration.1.abs_diff(duration.0) as usize + 1) as i64,
+                hold: hold.0 + rng.below(hold.1.abs_diff(hold.0) + 1),
+                band_side: self.band_side[rng.below(self.band_side.len())],
+            })
+            .collect::<Vec<_>>();
+        specs.sort_by_key(|spec| spec.duration);
+        Ok(specs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    Sharpe,
+    Sortino,
+    Calmar,
+    AnnualReturn,
+    InformationRatio,
+}
+
+impl Objective {
+    pub fn value(&self, metrics: &Metrics) -> f64 {
+        let value = match self {
+            Objective::Sharpe => metrics.sharpe,
+            Objective::Sortino => metrics.sortino,
+            Objective::Calmar => metrics.calmar,
+            Objective::AnnualReturn => metrics.annual_return,
+            Objective::InformationRatio => metrics.information_ratio.unwrap_or(f64::NAN),
+        };
+        // a spec that never traded ranks last
+        if value.is_finite() {
+            value
+        } else {
+            f64::NEG_INFINITY
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepSpec {
+    pub interval: Interval,
+    pub fill: Fill,
+    pub costs: Option<CostModel>,
+    pub capital: f64,
+    pub objective: Objective,
+    pub benchmark: Option<String>,
+    pub threads: usize,
+}
+
+impl SweepSpec {
+    pub fn new(interval: Interval, objective: Objective) -> Self {
+        Self {
+            interval,
+            fill: Fill::NextOpen,
+            costs: None,
+            capital: 10_000f64,
+            objective,
+            benchmark: None,
+            threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+}
+
+// The candles (`openTime`, ohlcv) and their rolling stats by duration.
+pub struct Cache {
+    candles: DataFrame,
+    stats: Mutex<HashMap<i64, DataFrame>>,
+}
+
+impl Cache {
+    pub fn new(candles: DataFrame) -> Self {
+        Self { candles, stats: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn rolling_stats(&self, duration: i64) -> Result<DataFrame> {
+        if let Some(df) = self.stats.lock().unwrap().get(&duration) {
+            return Ok(df.clone());
+        }
+        let df = rolling_stats(self.candles.clone().lazy(), duration).collect()?;
+        self.stats.lock().unwrap().insert(duration, df.clone());
+        Ok(df)
+    }
+
+    pub fn timestamps(&self) -> Result<Vec<i64>> {
+        let timestamp = self.candles.column("openTime")?.cast(&DataType::Int64)?;
+        Ok(timestamp.i64()?.into_no_null_iter().collect())
+    }
+
+    pub fn height(&self) -> usize {
+        self.candles.height()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trial {
+    pub signal: SignalSpec,
+    pub metrics: Metrics,
+    pub returns: Vec<f64>,  // bar returns of the backtest, after costs if any
+    pub position: Vec<f64>, // held over each bar
+}
+
+// Backtest of one spec, over the rows `rows` of the candles when given. The position is only decided
+// within the rows, the rolling stats look back past their start.
+pub fn evaluate(cache: &Cache, signal: &SignalSpec, spec: &SweepSpec, rows: Option<Range<usize>>) -> Result<Trial> {
+    let df = signals(cache.rolling_stats(signal.duration)?.lazy(), signal.sigma, signal.target_pnl).collect()?;
+    let df = match rows {
+        Some(rows) => df.slice(rows.start as i64, rows.end - rows.start),
+        None => df,
+    };
+    let backtest_spec = BacktestSpec::new("position", spec.fill);
+    let mut df = backtest(with_position(&df, signal)?.lazy(), &backtest_spec).collect()?;
+    if let Some(costs) = &spec.costs {
+        df = with_costs(&df, &backtest_spec, costs, spec.capital)?.0;
+    }
+    let metrics = metrics_from_frame(&df, spec.interval, spec.benchmark.as_deref())?;
+    let column = if spec.costs.is_some() { "net return" } else { "strategy return" };
+    let returns = f64_column(&df, column)?;
+    let position = f64_column(&df, "held position")?;
+    Ok(Trial { signal: *signal, metrics, returns, position })
+}
+
+// Evaluates every spec on `spec.threads` threads, in the order of `signals`.
+pub fn sweep(cache: &Cache, signals: &[SignalSpec], spec: &SweepSpec, rows: Option<Range<usize>>) -> Result<Vec<Trial>> {
+    // the rolling stats up front, so that threads don't race to compute the same duration
+    let mut durations = signals.iter().map(|s| s.duration).collect::<Vec<_>>();
+    durations.dedup();
+    for duration in durations {
+        cache.rolling_stats(duration)?;
+    }
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(signals.len()));
+    std::thread::scope(|scope| {
+        for _ in 0..spec.threads.max(1) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= signals.len() {
+                    break;
+                }
+                let trial = evaluate(cache, &signals[i], spec, rows.clone());
+                results.lock().unwrap().push((i, trial));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, trial)| trial).collect()
+}
+
+// blocks of the combinatorially symmetric cross-validation behind `pbo`
+pub const CSCV_BLOCKS: usize = 16;
+
+// One row per trial, best objective first. `psr` is the probability that the Sharpe ratio is positive,
+// `dsr` that it beats the best of as many trials without skill and `pbo` the probability of backtest
+// overfitting of the whole sweep.
+pub fn trials_to_frame(trials: &[Trial], objective: Objective) -> Result<DataFrame> {
+    let returns = trials.iter().map(|t| t.returns.clone()).collect::<Vec<_>>();
+    let dsr = deflated_sharpe(&returns);
+    let pbo = probability_of_overfitting(&returns, CSCV_BLOCKS);
+
+    let mut order = (0..trials.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| {
+        objective.value(&trials[*b].metrics).partial_cmp(&objective.value(&trials[*a].metrics)).unwrap()
+    });
+    let trials = order.iter().map(|i| &trials[*i]).collect::<Vec<_>>();
+    let signal = |f: fn(&SignalSpec) -> f64| trials.iter().map(|t| f(&t.signal)).collect::<Vec<_>>();
+    let metric = |f: fn(&Metrics) -> f64| trials.iter().map(|t| f(&t.metrics)).collect::<Vec<_>>();
+
+    DataFrame::new(vec![
+        Series::new("trial", order.iter().map(|i| *i as u32).collect::<Vec<_>>()),
+        Series::new("sigma", signal(|s| s.sigma)),
+        Series::new("target pnl", signal(|s| s.target_pnl)),
+        Series::new("duration", trials.iter().map(|t| t.signal.duration).collect::<Vec<_>>()),
+        Series::new("hold", trials.iter().map(|t| t.signal.hold as u32).collect::<Vec<_>>()),
+        Series::new("band side", trials.iter().map(|t| format!("{:?}", t.signal.band_side)).collect::<Vec<_>>()),
+        Series::new("objective", trials.iter().map(|t| objective.value(&t.metrics)).collect::<Vec<_>>()),
+        Series::new("total return", metric(|m| m.total_return)),
+        Series::new("annual return", metric(|m| m.annual_return)),
+        Series::new("annual volatility", metric(|m| m.annual_volatility)),
+        Series::new("sharpe", metric(|m| m.sharpe)),
+        Series::new("sortino", metric(|m| m.sortino)),
+        Series::new("calmar", metric(|m| m.calmar)),
+        Series::new("information ratio", trials.iter().map(|t| t.metrics.information_ratio).collect::<Vec<_>>()),
+        Series::new("max drawdown", metric(|m| m.max_drawdown)),
+        Series::new("trades", trials.iter().map(|t| t.metrics.trades as u32).collect::<Vec<_>>()),
+        Series::new("hit rate", metric(|m| m.hit_rate)),
+        Series::new("profit factor", metric(|m| m.profit_factor)),
+        Series::new("turnover", metric(|m| m.turnover)),
+        Series::new("exposure", metric(|m| m.exposure)),
+        Series::new("psr", trials.iter().map(|t| probabilistic_sharpe(&t.returns, 0f64)).collect::<Vec<_>>()),
+        Series::new("dsr", order.iter().map(|i| dsr[*i]).collect::<Vec<_>>()),
+        Series::new("pbo", vec![pbo; trials.len()]),
+    ])
+}
+
+pub fn write_table(df: &mut DataFrame, file_name: &str) -> Result<()> {
+    let file = std::fs::File::create(file_name)?;
+    CsvWriter::new(file).has_header(true).finish(df)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candles(n: usize) -> DataFrame {
+        // a drifting wave with a volume burst every 50 bars
+        let close = (0..n).map(|i| 100.0 * (1.0 + 0.0005 * i as f64) + 2.0 * (i as f64 / 7.0).sin()).collect::<Vec<_>>();
+        df![
+            "openTime" => (0..n as i64).map(|i| i * 900_000).collect::<Vec<_>>(),
+            "open" => close.iter().enumerate().map(|(i, c)| if i == 0 { *c } else { close[i - 1] }).collect::<Vec<_>>(),
+            "high" => close.iter().map(|c| c + 0.5).collect::<Vec<_>>(),
+            "low" => close.iter().map(|c| c - 0.5).collect::<Vec<_>>(),
+            "close" => close.clone(),
+            "volume" => (0..n).map(|i| if i % 50 == 49 { 10.0 } else { 1.0 + (i % 3) as f64 * 0.1 }).collect::<Vec<_>>(),
+        ].unwrap()
+    }
+
+    #[test]
+    fn test_sweep() {
+        let cache = Cache::new(candles(400));
+        let grid = SignalGrid { sigma: vec![1.0, 2.0], duration: vec![10, 20], hold: vec![5, 10], ..Default::default() };
+        let specs = grid.specs();
+        assert_eq!(specs.len(), 8);
+        let spec = SweepSpec { threads: 3, ..SweepSpec::new(Interval::M15, Objective::Sharpe) };
+
+        let trials = sweep(&cache, &specs, &spec, None).unwrap();
+        assert_eq!(cache.stats.lock().unwrap().len(), 2);
+        // same results as one at a time, in the order of the specs
+        let single = evaluate(&cache, &specs[5], &spec, None).unwrap();
+        assert_eq!(trials[5].signal, specs[5]);
+        assert_eq!(trials[5].metrics, single.metrics);
+        assert!(trials.iter().any(|t| t.metrics.trades > 0));
+
+        let table = trials_to_frame(&trials, Objective::Sharpe).unwrap();
+        assert_eq!(table.height(), 8);
+        let objective = table.column("objective").unwrap().f64().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert!(objective.windows(2).all(|w| w[0] >= w[1]));
+        for column in ["psr", "dsr", "pbo"] {
+            let values = table.column(column).unwrap().f64().unwrap();
+            assert!(values.into_iter().flatten().all(|p| (0.0..=1.0).contains(&p) || p.is_nan()));
+        }
+
+        let sampled = grid.sample(5, 1).unwrap();
+        assert_eq!(sampled, grid.sample(5, 1).unwrap());
+        assert!(sampled.iter().all(|s| (1.0..=2.0).contains(&s.sigma) && (10..=20).contains(&s.duration)));
+    }
+
+    #[test]
+    fn test_sample() {
+        // in any order, a single value always drawn
+        let grid = SignalGrid { duration: vec![30, 5, 10], hold: vec![4], ..SignalGrid::default() };
+        let sampled = grid.sample(200, 2).unwrap();
+        assert!(sampled.iter().all(|s| (5..=30).contains(&s.duration) && s.hold == 4));
+        assert!(sampled.iter().any(|s| s.duration == 5) && sampled.iter().any(|s| s.duration == 30));
+
+        let empty = [
+            SignalGrid { sigma: vec![], ..grid.clone() },
+            SignalGrid { target_pnl: vec![], ..grid.clone() },
+            SignalGrid { duration: vec![], ..grid.clone() },
+            SignalGrid { hold: vec![], ..grid.clone() },
+            SignalGrid { band_side: vec![], ..grid.clone() },
+        ];
+        for grid in empty {
+            assert!(matches!(grid.sample(5, 1), Err(PolarsError::NoData(_))), "{:?}", grid);
+        }
+    }
+}
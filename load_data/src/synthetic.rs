@@ -0,0 +1,285 @@
+use polars::prelude::*;
+
+use crate::data::{Interval, Trend};
+use crate::random::Rng;
+use crate::stats;
+
+// Seeded synthetic candles in the schema of the klines (`openTime`, ohlcv) with their ground truth:
+// the regime and its trend, and where volume spikes were injected. Model parameters are per bar.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Regime {
+    pub drift: f64,
+    pub volatility: f64,
+}
+
+impl Regime {
+    pub fn trend(&self) -> Trend {
+        if self.drift > 0f64 {
+            Trend::Bull
+        } else if self.drift < 0f64 {
+            Trend::Bear
+        } else {
+            Trend::Unknown
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Model {
+    Gbm(Regime),
+    // variance_t = omega + alpha * shock_{t-1}^2 + beta * variance_{t-1}
+    Garch { drift: f64, omega: f64, alpha: f64, beta: f64 },
+    // Merton: a Poisson number of normal jumps in log price added to the diffusion
+    JumpDiffusion { regime: Regime, intensity: f64, jump_mean: f64, jump_std: f64 },
+    // Markov chain over the regimes, `transition[i][j]` from regime i to regime j
+    RegimeSwitching { regimes: Vec<Regime>, transition: Vec<Vec<f64>> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spikes {
+    pub probability: f64,
+    pub multiplier: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticSpec {
+    pub model: Model,
+    pub bars: usize,
+    pub interval: Interval,
+    pub start: i64, // open time of the first bar in ms
+    pub price: f64,
+    pub volume: f64,       // median volume of a bar
+    pub volume_noise: f64, // std of the log volume
+    pub spikes: Option<Spikes>,
+    pub steps: usize, // intrabar steps making the high and the low
+    pub seed: u64,
+}
+
+impl SyntheticSpec {
+    pub fn new(model: Model, bars: usize, seed: u64) -> Self {
+        Self {
+            model,
+            bars,
+            interval: Interval::M15,
+            start: 1_648_771_200_000, // 2022-04-01
+            price: 40_000f64,
+            volume: 1_000f64,
+            volume_noise: 0.3,
+            spikes: None,
+            steps: 8,
+            seed,
+        }
+    }
+}
+
+// log return, volatility and regime of each bar
+fn log_returns(model: &Model, bars: usize, rng: &mut Rng) -> (Vec<f64>, Vec<f64>, Vec<usize>) {
+    let (mut returns, mut volatility, mut regimes) = (Vec::with_capacity(bars), Vec::with_capacity(bars), Vec::with_capacity(bars));
+    match model {
+        Model::Gbm(regime) => {
+            for _ in 0..bars {
+                returns.push(regime.drift - regime.volatility.powi(2) / 2f64 + regime.volatility * rng.normal());
+                volatility.push(regime.volatility);
+                regimes.push(0);
+            }
+        }
+        Model::Garch { drift, omega, alpha, beta } => {
+            let mut variance = omega / (1f64 - alpha - beta).max(1e-6);
+            let mut shock = 0f64;
+            for _ in 0..bars {
+                variance = omega + alpha * shock * shock + beta * variance;
+                shock = variance.sqrt() * rng.normal();
+                returns.push(drift + shock);
+                volatility.push(variance.sqrt());
+                regimes.push(0);
+            }
+        }
+        Model::JumpDiffusion { regime, intensity, jump_mean, jump_std } => {
+            for _ in 0..bars {
+                // Knuth's Poisson draw, the intensity per bar is small
+                let (limit, mut product, mut jumps) = ((-intensity).exp(), rng.uniform(), 0);
+                while product > limit {
+                    product *= rng.uniform();
+                    jumps += 1;
+                }
+                let jump = (0..jumps).map(|_| jump_mean + jump_std * rng.normal()).sum::<f64>();
+                returns.push(regime.drift - regime.volatility.powi(2) / 2f64 + regime.volatility * rng.normal() + jump);
+                volatility.push(regime.volatility);
+                regimes.push(0);
+            }
+        }
+        Model::RegimeSwitching { regimes: states, transition } => {
+            let mut state = 0;
+            for _ in 0..bars {
+                let regime = states[state];
+                returns.push(regime.drift - regime.volatility.powi(2) / 2f64 + regime.volatility * rng.normal());
+                volatility.push(regime.volatility);
+                regimes.push(state);
+                let u = rng.uniform();
+                let mut cumulated = 0f64;
+                state = transition[state]
+                    .iter()
+                    .position(|p| {
+                        cumulated += p;
+                        u < cumulated
+                    })
+                    .unwrap_or(state);
+            }
+        }
+    }
+    (returns, volatility, regimes)
+}
+
+fn regime_of(model: &Model, state: usize) -> Option<Regime> {
+    match model {
+        Model::Gbm(regime) | Model::JumpDiffusion { regime, .. } => Some(*regime),
+        Model::Garch { drift, .. } => Some(Regime { drift: *drift, volatility: f64::NAN }),
+        Model::RegimeSwitching { regimes, .. } => regimes.get(state).copied(),
+    }
+}
+
+pub fn generate(spec: &SyntheticSpec) -> Result<DataFrame> {
+    let mut rng = Rng::new(spec.seed);
+    let (returns, volatility, regimes) = log_returns(&spec.model, spec.bars, &mut rng);
+
+    let n = spec.bars;
+    let (mut open, mut high, mut low, mut close) = (Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n), Vec::with_capacity(n));
+    let (mut volume, mut spiked) = (Vec::with_capacity(n), Vec::with_capacity(n));
+    let mut price = spec.price;
+    let steps = spec.steps.max(1);
+    for (r, vol) in returns.iter().zip(&volatility) {
+        // a brownian bridge from the open to the close through the intrabar steps
+        let step_std = vol / (steps as f64).sqrt();
+        let mut path = vec![0f64; steps + 1];
+        for s in 1..=steps {
+            path[s] = path[s - 1] + step_std * rng.normal();
+        }
+        let end = path[steps];
+        let (mut hi, mut lo) = (price, price);
+        for (s, x) in path.iter().enumerate() {
+            let p = price * (x - end * s as f64 / steps as f64 + r * s as f64 / steps as f64).exp();
+            hi = hi.max(p);
+            lo = lo.min(p);
+        }
+        open.push(price);
+        price *= r.exp();
+        close.push(price);
+        high.push(hi.max(price));
+        low.push(lo.min(price));
+
+        let spike = spec.spikes.as_ref().map(|s| rng.bernoulli(s.probability)).unwrap_or(false);
+        let multiplier = if spike { spec.spikes.as_ref().unwrap().multiplier } else { 1f64 };
+        volume.push(spec.volume * (spec.volume_noise * rng.normal()).exp() * multiplier);
+        spiked.push(spike);
+    }
+
+    let trend = regimes
+        .iter()
+        .map(|state| regime_of(&spec.model, *state).map(|r| r.trend()).unwrap_or(Trend::Unknown).name())
+        .collect::<Vec<_>>();
+    DataFrame::new(vec![
+        Series::new("openTime", (0..n as i64).map(|i| spec.start + i * spec.interval.millis()).collect::<Vec<_>>()),
+        Series::new("open", open),
+        Series::new("high", high),
+        Series::new("low", low),
+        Series::new("close", close),
+        Series::new("volume", volume),
+        Series::new("regime", regimes.iter().map(|s| *s as u32).collect::<Vec<_>>()),
+        Series::new("true trend", trend).cast(&Trend::dtype())?,
+        Series::new("volume spike", spiked),
+    ])
+}
+
+// Share of bars flagged by `abnormal volume` at `sigma` when the volume is lognormal with
+// `volume_noise` and no spikes, against the true mean and std of the volume.
+pub fn expected_false_positive_rate(volume_noise: f64, sigma: f64) -> f64 {
+    let s2 = volume_noise * volume_noise;
+    let mean = (s2 / 2f64).exp();
+    let std = ((s2.exp() - 1f64) * s2.exp()).sqrt();
+    1f64 - stats::normal_cdf((mean + sigma * std).ln() / volume_noise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{aggregate, bool_column, f64_column, Candles};
+    use crate::labeling::{label_events, BarrierSpec};
+
+    fn log_returns_of(df: &DataFrame) -> Vec<f64> {
+        let close = f64_column(df, "close").unwrap();
+        close.windows(2).map(|w| (w[1] / w[0]).ln()).collect()
+    }
+
+    #[test]
+    fn test_models() {
+        let gbm = Model::Gbm(Regime { drift: 0.0005, volatility: 0.01 });
+        let df = generate(&SyntheticSpec::new(gbm.clone(), 5000, 1)).unwrap();
+        assert_eq!(df, generate(&SyntheticSpec::new(gbm, 5000, 1)).unwrap());
+        let r = log_returns_of(&df);
+        assert!((stats::std(&r) - 0.01).abs() < 0.0005);
+        let (high, low) = (f64_column(&df, "high").unwrap(), f64_column(&df, "low").unwrap());
+        let (open, close) = (f64_column(&df, "open").unwrap(), f64_column(&df, "close").unwrap());
+        assert!((0..5000).all(|i| high[i] >= open[i].max(close[i]) && low[i] <= open[i].min(close[i])));
+
+        // volatility clusters under garch: squared returns are autocorrelated
+        let garch = Model::Garch { drift: 0.0, omega: 1e-6, alpha: 0.1, beta: 0.85 };
+        let r = log_returns_of(&generate(&SyntheticSpec::new(garch, 5000, 2)).unwrap());
+        let squared = r.iter().map(|x| x * x).collect::<Vec<_>>();
+        assert!(stats::correlation(&squared[1..], &squared[..squared.len() - 1]) > 0.1);
+
+        // jumps fatten the tails
+        let jumps = Model::JumpDiffusion { regime: Regime { drift: 0.0, volatility: 0.005 }, intensity: 0.02, jump_mean: 0.0, jump_std: 0.03 };
+        let r = log_returns_of(&generate(&SyntheticSpec::new(jumps, 5000, 3)).unwrap());
+        assert!(stats::kurtosis(&r) > 6.0);
+
+        // the ground truth follows the regimes
+        let switching = Model::RegimeSwitching {
+            regimes: vec![Regime { drift: 0.002, volatility: 0.005 }, Regime { drift: -0.002, volatility: 0.01 }],
+            transition: vec![vec![0.98, 0.02], vec![0.03, 0.97]],
+        };
+        let df = generate(&SyntheticSpec::new(switching, 3000, 4)).unwrap();
+        let trend = Trend::from_series(df.column("true trend").unwrap()).unwrap();
+        let regime = df.column("regime").unwrap().u32().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert!(regime.contains(&0) && regime.contains(&1));
+        assert!(regime.iter().zip(&trend).all(|(r, t)| *t == Some(if *r == 0 { Trend::Bull } else { Trend::Bear })));
+    }
+
+    #[test]
+    fn test_abnormal_volume_rate() {
+        let gbm = Model::Gbm(Regime { drift: 0.0, volatility: 0.002 });
+        let spec = SyntheticSpec { volume_noise: 0.4, ..SyntheticSpec::new(gbm, 20_000, 5) };
+        let df = aggregate(generate(&spec).unwrap().lazy(), 2.0, 0.01, 500).collect().unwrap();
+        let flags = bool_column(&df, "abnormal volume").unwrap();
+        let rate = flags[501..].iter().filter(|f| **f).count() as f64 / (flags.len() - 501) as f64;
+        let expected = expected_false_positive_rate(0.4, 2.0);
+        assert!((rate - expected).abs() < 0.3 * expected, "{} against {}", rate, expected);
+
+        // injected spikes are caught
+        let spec = SyntheticSpec { spikes: Some(Spikes { probability: 0.01, multiplier: 10.0 }), ..spec };
+        let source = generate(&spec).unwrap();
+        let df = aggregate(source.clone().lazy(), 2.0, 0.01, 500).collect().unwrap();
+        let flags = bool_column(&df, "abnormal volume").unwrap();
+        let spikes = bool_column(&source, "volume spike").unwrap();
+        // the flag of a bar is about the volume of the previous one
+        let caught = (501..spikes.len()).filter(|i| spikes[i - 1]).filter(|i| flags[*i]).count();
+        let total = (501..spikes.len()).filter(|i| spikes[i - 1]).count();
+        assert!(caught as f64 / total as f64 > 0.95);
+    }
+
+    #[test]
+    fn test_labels_follow_regimes() {
+        let switching = Model::RegimeSwitching {
+            regimes: vec![Regime { drift: 0.003, volatility: 0.004 }, Regime { drift: -0.003, volatility: 0.004 }],
+            transition: vec![vec![0.995, 0.005], vec![0.005, 0.995]],
+        };
+        let source = generate(&SyntheticSpec::new(switching, 4000, 6)).unwrap();
+        let df = aggregate(source.clone().lazy(), 2.0, 0.01, 20).collect().unwrap();
+        let candles = Candles::from_frame(&df).unwrap();
+        let events = (0..candles.len()).map(|i| i % 25 == 0).collect::<Vec<_>>();
+        let labels = label_events(&candles, &BarrierSpec::fixed(0.02, 0.02, 40), &events);
+        let truth = Trend::from_series(source.column("true trend").unwrap()).unwrap();
+        let agree = labels.iter().filter(|l| Some(l.barrier.trend()) == truth[l.event]).count();
+        assert!(agree as f64 / labels.len() as f64 > 0.8, "{} of {}", agree, labels.len());
+    }
+}
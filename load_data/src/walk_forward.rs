@@ -0,0 +1,226 @@
+use std::ops::Range;
+
+use polars::prelude::*;
+
+use crate::data::timestamp_series;
+use crate::metrics::{equity, metrics, Metrics};
+use crate::signal::SignalSpec;
+use crate::stats;
+use crate::sweep::{evaluate, sweep, Cache, SweepSpec};
+
+// Walk-forward optimization: the best spec of the sweep over each train window is traded over the
+// test window right after it, and the test windows are stitched into one out of sample run.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    Rolling,  // the train window keeps its length
+    Anchored, // the train window always starts at the first bar
+}
+
+#[derive(Debug, Clone)]
+pub struct WalkForwardSpec {
+    pub train: usize, // bars
+    pub test: usize,
+    pub window: Window,
+    pub sweep: SweepSpec,
+}
+
+impl WalkForwardSpec {
+    pub fn days(train: f64, test: f64, window: Window, sweep: SweepSpec) -> Self {
+        let bars = |days: f64| (days * 24f64 * 60f64 / sweep.interval.minutes() as f64).round() as usize;
+        Self { train: bars(train), test: bars(test), window, sweep }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+    pub train: Range<usize>,
+    pub test: Range<usize>,
+}
+
+// test windows back to back up to the last bar, the last one may be shorter
+pub fn folds(bars: usize, spec: &WalkForwardSpec) -> Vec<Fold> {
+    let mut folds = Vec::new();
+    let mut start = spec.train;
+    while start < bars && spec.test > 0 {
+        let train = match spec.window {
+            Window::Rolling => start - spec.train..start,
+            Window::Anchored => 0..start,
+        };
+        folds.push(Fold { train, test: start..(start + spec.test).min(bars) });
+        start += spec.test;
+    }
+    folds
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowResult {
+    pub fold: Fold,
+    pub best: SignalSpec,
+    pub in_sample: Metrics,
+    pub out_of_sample: Metrics,
+    pub returns: Vec<f64>, // over the test window
+    pub position: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WalkForward {
+    pub windows: Vec<WindowResult>,
+    pub timestamp: Vec<i64>,
+    pub returns: Vec<f64>, // stitched out of sample
+    pub position: Vec<f64>,
+    pub metrics: Metrics,  // of the stitched returns
+}
+
+pub fn walk_forward(cache: &Cache, signals: &[SignalSpec], spec: &WalkForwardSpec) -> Result<WalkForward> {
+    let timestamp = cache.timestamps()?;
+    let objective = spec.sweep.objective;
+    let mut windows = Vec::new();
+    for fold in folds(cache.height(), spec) {
+        let trials = sweep(cache, signals, &spec.sweep, Some(fold.train.clone()))?;
+        let best = trials
+            .iter()
+            .max_by(|a, b| objective.value(&a.metrics).partial_cmp(&objective.value(&b.metrics)).unwrap())
+            .ok_or_else(|| PolarsError::NoData("no spec to walk forward".into()))?;
+        let test = evaluate(cache, &best.signal, &spec.sweep, Some(fold.test.clone()))?;
+        windows.push(WindowResult {
+            fold,
+            best: best.signal,
+            in_sample: best.metrics.clone(),
+            out_of_sample: test.metrics,
+            returns: test.returns,
+            position: test.position,
+        });
+    }
+
+    let returns = windows.iter().flat_map(|w| w.returns.iter().copied()).collect::<Vec<_>>();
+    let stamps = windows.iter().flat_map(|w| timestamp[w.fold.test.clone()].iter().copied()).collect::<Vec<_>>();
+    let position = windows.iter().flat_map(|w| w.position.iter().copied()).collect::<Vec<_>>();
+    let turnover = (0..position.len())
+        .map(|i| (position[i] - if i == 0 { 0f64 } else { position[i - 1] }).abs())
+        .collect::<Vec<_>>();
+    let metrics = metrics(&returns, &position, &turnover, None, spec.sweep.interval);
+    Ok(WalkForward { windows, timestamp: stamps, returns, position, metrics })
+}
+
+type Parameter = (&'static str, fn(&SignalSpec) -> f64);
+
+impl WalkForward {
+    // one row per window with the chosen spec and its in and out of sample objective
+    pub fn windows_frame(&self, timestamp: &[i64], spec: &WalkForwardSpec) -> Result<DataFrame> {
+        let objective = spec.sweep.objective;
+        let windows = &self.windows;
+        let stamp = |f: fn(&Fold) -> usize| windows.iter().map(|w| timestamp[f(&w.fold)]).collect::<Vec<_>>();
+        DataFrame::new(vec![
+            timestamp_series("train start", stamp(|f| f.train.start))?,
+            timestamp_series("test start", stamp(|f| f.test.start))?,
+            timestamp_series("test end", stamp(|f| f.test.end - 1))?,
+            Series::new("sigma", windows.iter().map(|w| w.best.sigma).collect::<Vec<_>>()),
+            Series::new("target pnl", windows.iter().map(|w| w.best.target_pnl).collect::<Vec<_>>()),
+            Series::new("duration", windows.iter().map(|w| w.best.duration).collect::<Vec<_>>()),
+            Series::new("hold", windows.iter().map(|w| w.best.hold as u32).collect::<Vec<_>>()),
+            Series::new("band side", windows.iter().map(|w| format!("{:?}", w.best.band_side)).collect::<Vec<_>>()),
+            Series::new("in sample", windows.iter().map(|w| objective.value(&w.in_sample)).collect::<Vec<_>>()),
+            Series::new("out of sample", windows.iter().map(|w| objective.value(&w.out_of_sample)).collect::<Vec<_>>()),
+            Series::new("out of sample return", windows.iter().map(|w| w.out_of_sample.total_return).collect::<Vec<_>>()),
+        ])
+    }
+
+    pub fn equity_frame(&self) -> Result<DataFrame> {
+        let equity = equity(&self.returns);
+        DataFrame::new(vec![
+            timestamp_series("timestamp", self.timestamp.clone())?,
+            Series::new("position", self.position.clone()),
+            Series::new("return", self.returns.clone()),
+            Series::new("equity", equity),
+        ])
+    }
+
+    // Stability of the chosen parameters across windows: spread and how often they changed.
+    pub fn stability(&self) -> Result<DataFrame> {
+        let parameters: [Parameter; 5] = [
+            ("sigma", |s| s.sigma),
+            ("target pnl", |s| s.target_pnl),
+            ("duration", |s| s.duration as f64),
+            ("hold", |s| s.hold as f64),
+            ("band side", |s| s.band_side.side(true)), // 1 for momentum, -1 for reversion
+        ];
+        let (mut names, mut means, mut stds, mut cvs, mut distinct, mut changes) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (name, get) in parameters {
+            let values = self.windows.iter().map(|w| get(&w.best)).collect::<Vec<_>>();
+            let (mean, std) = (stats::mean(&values), stats::std(&values));
+            let mut unique = values.clone();
+            unique.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            unique.dedup();
+            names.push(name);
+            means.push(mean);
+            stds.push(std);
+            cvs.push(std / mean.abs());
+            distinct.push(unique.len() as u32);
+            changes.push(values.windows(2).filter(|w| w[0] != w[1]).count() as u32);
+        }
+        DataFrame::new(vec![
+            Series::new("parameter", names),
+            Series::new("mean", means),
+            Series::new("std", stds),
+            Series::new("coefficient of variation", cvs),
+            Series::new("distinct", distinct),
+            Series::new("changes", changes),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Interval;
+    use crate::sweep::{Objective, SignalGrid};
+
+    #[test]
+    fn test_folds() {
+        let spec = WalkForwardSpec {
+            train: 100,
+            test: 40,
+            window: Window::Rolling,
+            sweep: SweepSpec::new(Interval::M15, Objective::Sharpe),
+        };
+        let rolling = folds(250, &spec);
+        assert_eq!(rolling.len(), 4);
+        assert_eq!(rolling[1], Fold { train: 40..140, test: 140..180 });
+        assert_eq!(rolling[3].test, 220..250);
+        let anchored = folds(250, &WalkForwardSpec { window: Window::Anchored, ..spec.clone() });
+        assert_eq!(anchored[2].train, 0..180);
+        assert_eq!(WalkForwardSpec::days(30.0, 7.0, Window::Rolling, spec.sweep).test, 7 * 96);
+    }
+
+    #[test]
+    fn test_walk_forward() {
+        let n = 600;
+        let close = (0..n).map(|i| 100.0 + 3.0 * (i as f64 / 11.0).sin()).collect::<Vec<_>>();
+        let candles = df![
+            "openTime" => (0..n as i64).map(|i| i * 900_000).collect::<Vec<_>>(),
+            "open" => close.clone(),
+            "high" => close.iter().map(|c| c + 0.4).collect::<Vec<_>>(),
+            "low" => close.iter().map(|c| c - 0.4).collect::<Vec<_>>(),
+            "close" => close,
+            "volume" => (0..n).map(|i| if i % 37 == 0 { 8.0 } else { 1.0 + (i % 4) as f64 * 0.1 }).collect::<Vec<_>>(),
+        ].unwrap();
+        let cache = Cache::new(candles);
+        let grid = SignalGrid { sigma: vec![1.0, 2.0], hold: vec![3, 9], ..Default::default() };
+        let spec = WalkForwardSpec { train: 200, test: 100, window: Window::Anchored, sweep: SweepSpec::new(Interval::M15, Objective::Sharpe) };
+
+        let result = walk_forward(&cache, &grid.specs(), &spec).unwrap();
+        assert_eq!(result.windows.len(), 4);
+        assert_eq!(result.returns.len(), 400);
+        assert_eq!(result.timestamp[0], 200 * 900_000);
+        // the stitched returns are the ones of each test window
+        assert_eq!(result.returns[100..200], result.windows[1].returns[..]);
+
+        let timestamp = cache.timestamps().unwrap();
+        assert_eq!(result.windows_frame(&timestamp, &spec).unwrap().height(), 4);
+        let stability = result.stability().unwrap();
+        assert_eq!(stability.height(), 5);
+        assert_eq!(result.equity_frame().unwrap().height(), 400);
+    }
+}